@@ -0,0 +1,293 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use mlua::prelude::*;
+
+use crate::vim::Vim;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+/// Entries that haven't been picked for this long are dropped when pruning.
+const MAX_AGE: u64 = 90 * DAY;
+/// Scales an entry's frecency into a bonus comparable to match scores.
+const BOOST_WEIGHT: f64 = 10.0;
+
+/// How often and how recently paths were picked, persisted between sessions
+/// as one `count<TAB>last used<TAB>path` line per entry.
+#[derive(Debug, Default, PartialEq)]
+pub struct Frecency {
+    entries: HashMap<String, Entry>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Entry {
+    count: u32,
+    last_used: u64,
+}
+
+impl Frecency {
+    /// Reads the store at `path`, starting out empty if it doesn't exist yet.
+    /// Lines that can't be parsed are skipped.
+    pub fn load(path: &Path) -> Frecency {
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        let entries = contents
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, '\t');
+                let count = fields.next()?.parse().ok()?;
+                let last_used = fields.next()?.parse().ok()?;
+                let key = fields.next()?;
+                Some((key.to_string(), Entry { count, last_used }))
+            })
+            .collect();
+
+        Frecency { entries }
+    }
+
+    /// Writes the store to `path` through a temporary file that then replaces it,
+    /// so another Neovim saving at the same time can't leave it half written.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let contents: String = self
+            .entries
+            .iter()
+            .map(|(key, entry)| format!("{}\t{}\t{}\n", entry.count, entry.last_used, key))
+            .collect();
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("frecency");
+        let temp = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+        fs::write(&temp, contents)?;
+        fs::rename(&temp, path).inspect_err(|_| {
+            let _ = fs::remove_file(&temp);
+        })
+    }
+
+    pub fn record(&mut self, key: &str, now: u64) {
+        let entry = self.entries.entry(key.to_string()).or_insert(Entry {
+            count: 0,
+            last_used: now,
+        });
+        entry.count += 1;
+        entry.last_used = now;
+    }
+
+    /// How often `key` was picked, weighted by how recently that last happened.
+    pub fn score(&self, key: &str, now: u64) -> f64 {
+        let Some(entry) = self.entries.get(key) else {
+            return 0.0;
+        };
+
+        let weight = match now.saturating_sub(entry.last_used) {
+            age if age < HOUR => 4.0,
+            age if age < DAY => 2.0,
+            age if age < WEEK => 1.0,
+            age if age < 4 * WEEK => 0.5,
+            _ => 0.25,
+        };
+        entry.count as f64 * weight
+    }
+
+    /// The ranking bonus for `key`, growing logarithmically with its score so a
+    /// frequently picked entry can't outrank a much better match.
+    pub fn boost(&self, key: &str, now: u64) -> i32 {
+        (BOOST_WEIGHT * self.score(key, now).ln_1p()).round() as i32
+    }
+
+    /// Ranking bonuses for every entry below `root`, keyed by their path relative to it.
+    pub fn boosts(&self, root: &Path, now: u64) -> HashMap<String, i32> {
+        self.entries
+            .keys()
+            .filter_map(|key| {
+                let relative = Path::new(key).strip_prefix(root).ok()?.to_str()?;
+                Some((relative.to_string(), self.boost(key, now)))
+            })
+            .collect()
+    }
+
+    /// Drops entries that haven't been picked for a long time or no longer exist.
+    pub fn prune(&mut self, now: u64) {
+        self.entries
+            .retain(|key, entry| now.saturating_sub(entry.last_used) < MAX_AGE && Path::new(key).exists());
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// The store as read when a picker opened, along with the boosts last computed
+/// from it, so that typing in the picker never goes back to the file system.
+#[derive(Clone, Default)]
+pub struct Boosts(Rc<RefCell<Option<Cached>>>);
+
+struct Cached {
+    frecency: Frecency,
+    now: u64,
+    scope: Option<PathBuf>,
+    boosts: Arc<HashMap<String, i32>>,
+}
+
+impl Boosts {
+    /// Ranking bonuses for every entry below `root`, keyed by their path relative to it.
+    pub fn below(&self, lua: &Lua, root: &Path) -> LuaResult<Arc<HashMap<String, i32>>> {
+        self.get(lua, root, |frecency, now| Ok(frecency.boosts(Path::new(&key(root)), now)))
+    }
+
+    /// The ranking bonuses made by `compute`. The store is only read the first
+    /// time, and `compute` only runs again once `scope` differs from the last call.
+    pub fn get(
+        &self, lua: &Lua, scope: &Path, compute: impl FnOnce(&Frecency, u64) -> LuaResult<HashMap<String, i32>>,
+    ) -> LuaResult<Arc<HashMap<String, i32>>> {
+        let mut cached = self.0.borrow_mut();
+        if cached.is_none() {
+            *cached = Some(Cached {
+                frecency: load(lua)?,
+                now: now(),
+                scope: None,
+                boosts: Arc::default(),
+            });
+        }
+
+        let cached = cached.as_mut().unwrap();
+        if cached.scope.as_deref() != Some(scope) {
+            cached.boosts = Arc::new(compute(&cached.frecency, cached.now)?);
+            cached.scope = Some(scope.to_path_buf());
+        }
+        Ok(cached.boosts.clone())
+    }
+}
+
+/// Where the store lives: `peek/frecency` under Neovim's state directory.
+pub fn store_path(lua: &Lua) -> LuaResult<PathBuf> {
+    let state = Vim::new(lua).stdpath("state")?;
+    Ok(PathBuf::from(state).join("peek").join("frecency"))
+}
+
+/// The key `path` is recorded under, so the same file is always counted once.
+pub fn key(path: &Path) -> String {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    path.to_string_lossy().into_owned()
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn load(lua: &Lua) -> LuaResult<Frecency> {
+    Ok(Frecency::load(&store_path(lua)?))
+}
+
+/// Remembers that `paths` were picked, dropping entries that have gone stale on
+/// the way. The store is read and written once however many paths there are.
+/// Failing to save it only shows a warning, so it never gets in the way of
+/// opening the files.
+pub fn record_all(lua: &Lua, paths: &[PathBuf]) -> LuaResult<()> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let store = store_path(lua)?;
+    let mut frecency = Frecency::load(&store);
+    let now = now();
+    frecency.prune(now);
+    for path in paths {
+        frecency.record(&key(path), now);
+    }
+    if let Err(e) = frecency.save(&store) {
+        Vim::new(lua).notify(&format!("peek: failed to save frecency: {}", e), "WARN")?;
+    }
+    Ok(())
+}
+
+pub fn clear(lua: &Lua, _: ()) -> LuaResult<()> {
+    let store = store_path(lua)?;
+    Frecency::default().save(&store).map_err(LuaError::external)
+}
+
+pub fn prune(lua: &Lua, _: ()) -> LuaResult<()> {
+    let store = store_path(lua)?;
+    let mut frecency = Frecency::load(&store);
+    frecency.prune(now());
+    frecency.save(&store).map_err(LuaError::external)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("frecency");
+        let mut frecency = Frecency::default();
+        frecency.record("/tmp/a.rs", 10);
+        frecency.record("/tmp/a.rs", 20);
+        frecency.record("/tmp/with\ttab.rs", 30);
+        frecency.save(&path).unwrap();
+
+        assert_eq!(Frecency::load(&path), frecency);
+        assert_eq!(Frecency::load(&path.with_file_name("missing")), Frecency::default());
+
+        Frecency::default().save(&path).unwrap();
+        assert_eq!(Frecency::load(&path), Frecency::default());
+        let files: Vec<_> = fs::read_dir(path.parent().unwrap()).unwrap().collect();
+        assert_eq!(files.len(), 1);
+    }
+
+    #[test]
+    fn test_score() {
+        let now = 100 * WEEK;
+        let mut frecency = Frecency::default();
+        frecency.record("/often", now - 2 * WEEK);
+        frecency.record("/often", now - 2 * WEEK);
+        frecency.record("/often", now - 2 * WEEK);
+        frecency.record("/recent", now - 1);
+        frecency.record("/old", now - 10 * WEEK);
+
+        assert_eq!(frecency.score("/often", now), 1.5);
+        assert_eq!(frecency.score("/recent", now), 4.0);
+        assert_eq!(frecency.score("/old", now), 0.25);
+        assert_eq!(frecency.score("/never", now), 0.0);
+        assert!(frecency.boost("/recent", now) > frecency.boost("/often", now));
+        assert_eq!(frecency.boost("/never", now), 0);
+    }
+
+    #[test]
+    fn test_boosts() {
+        let mut frecency = Frecency::default();
+        frecency.record("/project/src/lib.rs", 0);
+        frecency.record("/elsewhere/lib.rs", 0);
+
+        let boosts = frecency.boosts(Path::new("/project"), 0);
+        assert_eq!(boosts.keys().collect::<Vec<_>>(), vec!["src/lib.rs"]);
+    }
+
+    #[test]
+    fn test_prune() {
+        let existing = std::env::temp_dir();
+        let existing = existing.to_str().unwrap();
+        let now = MAX_AGE * 2;
+        let mut frecency = Frecency::default();
+        frecency.record(existing, now);
+        frecency.record("/does/not/exist", now);
+        frecency.record(&format!("{}/", existing), 0);
+
+        frecency.prune(now);
+        assert_eq!(frecency.entries.keys().collect::<Vec<_>>(), vec![existing]);
+
+        frecency.clear();
+        assert_eq!(frecency, Frecency::default());
+    }
+}
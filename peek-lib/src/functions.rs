@@ -1,59 +1,101 @@
+use crate::results;
 use crate::vim::{BufferDeleteOptions, Vim};
+use crate::worker;
 use crate::Config;
 use mlua::prelude::*;
 
 pub fn select_down(lua: &Lua, _: ()) -> LuaResult<()> {
-    let buffer = 0;
-    let vim = Vim::new(lua);
-    let config = Config::new(lua);
-    let total: i32 = vim.nvim_buf_get_var(buffer, "peek_results_count")?;
-    let offset: i32 = vim.nvim_buf_get_var(buffer, "peek_offset")?;
-    let limit: i32 = vim.nvim_buf_get_var(buffer, "peek_limit")?;
-    let cursor_position: i32 = vim.nvim_buf_get_var(buffer, "peek_cursor")?;
-    let next = cursor_position + 1;
-
-    if (offset + cursor_position) == total {
-        return Ok(());
+    let (position, total, _) = selection(lua)?;
+    match position < total {
+        true => select(lua, position + 1),
+        false if wrap(lua) => select(lua, 1),
+        false => Ok(()),
     }
+}
 
-    if next == limit {
-        vim.nvim_buf_set_var(buffer, "peek_offset", LuaValue::Integer((offset + 1).into()))?;
-        crate::render(lua).call(())?;
-        crate::highlight_selected_line(&vim, buffer, cursor_position)?;
-        config.on_refresh_callback()?;
-        return Ok(());
+pub fn select_up(lua: &Lua, _: ()) -> LuaResult<()> {
+    let (position, total, _) = selection(lua)?;
+    match position > 1 {
+        true => select(lua, position - 1),
+        false if wrap(lua) => select(lua, total),
+        false => Ok(()),
     }
+}
 
-    vim.nvim_buf_set_var(buffer, "peek_cursor", LuaValue::Integer(next.into()))?;
-    crate::highlight_selected_line(&vim, buffer, next)?;
-    config.on_refresh_callback()?;
+pub fn page_down(lua: &Lua, _: ()) -> LuaResult<()> {
+    let (position, _, page) = selection(lua)?;
+    select(lua, position + page)
+}
 
-    Ok(())
+pub fn page_up(lua: &Lua, _: ()) -> LuaResult<()> {
+    let (position, _, page) = selection(lua)?;
+    select(lua, position.saturating_sub(page))
 }
 
-pub fn select_up(lua: &Lua, _: ()) -> LuaResult<()> {
+pub fn select_first(lua: &Lua, _: ()) -> LuaResult<()> {
+    select(lua, 1)
+}
+
+pub fn select_last(lua: &Lua, _: ()) -> LuaResult<()> {
+    let (_, total, _) = selection(lua)?;
+    select(lua, total)
+}
+
+fn wrap(lua: &Lua) -> bool {
+    Config::new(lua).wrap.unwrap_or(false)
+}
+
+/// The selected position among all results, the number of results and how many fit in the window.
+fn selection(lua: &Lua) -> LuaResult<(usize, usize, usize)> {
     let buffer = 0;
     let vim = Vim::new(lua);
-    let config = Config::new(lua);
-    let cursor_position = vim.nvim_buf_get_var::<i32>(buffer, "peek_cursor")?;
-    let next = std::cmp::max(cursor_position - 1, 0);
-    let offset: i32 = vim.nvim_buf_get_var(buffer, "peek_offset")?;
+    let total: usize = vim.nvim_buf_get_var(buffer, "peek_results_count")?;
+    let offset: usize = vim.nvim_buf_get_var(buffer, "peek_offset")?;
+    let limit: usize = vim.nvim_buf_get_var(buffer, "peek_limit")?;
+    let cursor: usize = vim.nvim_buf_get_var(buffer, "peek_cursor")?;
 
-    if cursor_position == 2 && offset > 0 {
-        vim.nvim_buf_set_var(buffer, "peek_offset", LuaValue::Integer((offset - 1).into()))?;
-        crate::render(lua).call(())?;
+    Ok((offset + cursor, total, visible_rows(limit)))
+}
+
+/// Result lines that fit below the prompt in a window `limit` lines high.
+fn visible_rows(limit: usize) -> usize {
+    limit.saturating_sub(1).max(1)
+}
 
-        crate::highlight_selected_line(&vim, buffer, cursor_position)?;
-        config.on_refresh_callback()?;
+/// Selects the result at `position`, counting from 1 and clamped to the results,
+/// scrolling the list just enough to bring it into view.
+fn select(lua: &Lua, position: usize) -> LuaResult<()> {
+    let buffer = 0;
+    let vim = Vim::new(lua);
+    let config = Config::new(lua);
+    let total: usize = vim.nvim_buf_get_var(buffer, "peek_results_count")?;
+    let offset: usize = vim.nvim_buf_get_var(buffer, "peek_offset")?;
+    let limit: usize = vim.nvim_buf_get_var(buffer, "peek_limit")?;
+    if total == 0 {
         return Ok(());
     }
 
-    vim.nvim_buf_set_var(buffer, "peek_cursor", LuaValue::Integer(next.into()))?;
+    let (cursor, new_offset) = scroll(position.clamp(1, total), offset, visible_rows(limit));
+    vim.nvim_buf_set_var(buffer, "peek_cursor", LuaValue::Integer(cursor as i64))?;
+    if new_offset != offset {
+        vim.nvim_buf_set_var(buffer, "peek_offset", LuaValue::Integer(new_offset as i64))?;
+        crate::render(lua).call(())?;
+    }
 
-    crate::highlight_selected_line(&vim, buffer, next)?;
-    config.on_refresh_callback()?;
+    crate::highlight_selected_line(&vim, buffer, cursor as i32)?;
+    crate::preview::update(lua)?;
+    config.on_refresh_callback()
+}
 
-    Ok(())
+/// The cursor row and list offset that show `position` with `rows` result lines,
+/// keeping the current `offset` if the position is already visible.
+fn scroll(position: usize, offset: usize, rows: usize) -> (usize, usize) {
+    let offset = match position {
+        p if p <= offset => p - 1,
+        p if p > offset + rows => p - rows,
+        _ => offset,
+    };
+    (position - offset, offset)
 }
 
 pub fn exit(lua: &Lua, _: ()) -> LuaResult<()> {
@@ -61,6 +103,9 @@ pub fn exit(lua: &Lua, _: ()) -> LuaResult<()> {
     let buffer = vim.nvim_get_current_buf().unwrap();
     let window = vim.nvim_get_current_win().unwrap();
     let origin_window: usize = origin_window(lua, ())?;
+    crate::preview::close(lua, buffer)?;
+    results::remove(lua, buffer);
+    worker::stop(lua, buffer);
     vim.nvim_win_close(window, true)?;
     lua.load("vim.cmd('stopinsert')").eval()?;
     vim.nvim_buf_delete(
@@ -87,20 +132,82 @@ pub fn position(lua: &Lua, _: ()) -> LuaResult<usize> {
 }
 
 pub fn selected_value(lua: &Lua, _: ()) -> LuaResult<Option<mlua::Value>> {
-    let buffer = 0;
     let vim = Vim::new(lua);
+    let buffer = vim.nvim_get_current_buf()?;
     let cursor_position: usize = vim.nvim_buf_get_var(buffer, "peek_cursor")?;
     let offset: usize = vim.nvim_buf_get_var(buffer, "peek_offset")?;
-    let data: Vec<mlua::Value> = vim.nvim_buf_get_var(buffer, "peek_results")?;
 
-    match data.get(offset + cursor_position - 1) {
-        Some(v) => Ok(Some(v.clone())),
+    match (offset + cursor_position).checked_sub(1) {
+        Some(i) => results::value(lua, buffer, i),
         None => Ok(None),
     }
 }
 
+/// Every result of the current picker.
+pub fn all_values(lua: &Lua) -> LuaResult<Vec<mlua::Value<'_>>> {
+    let buffer = Vim::new(lua).nvim_get_current_buf()?;
+    results::values(lua, buffer, 0..usize::MAX)
+}
+
+/// The marked results, or the selected one if nothing is marked.
+pub fn selected_values(lua: &Lua, _: ()) -> LuaResult<Vec<mlua::Value<'_>>> {
+    let marked = marked_values(lua)?;
+    if !marked.is_empty() {
+        return Ok(marked);
+    }
+    Ok(selected_value(lua, ())?.into_iter().collect())
+}
+
+/// Marks the selected result, or unmarks it if it already was, and moves on to the next one.
+pub fn toggle_mark(lua: &Lua, _: ()) -> LuaResult<()> {
+    let vim = Vim::new(lua);
+    let buffer = vim.nvim_get_current_buf()?;
+    let cursor: usize = vim.nvim_buf_get_var(buffer, "peek_cursor")?;
+    let offset: usize = vim.nvim_buf_get_var(buffer, "peek_offset")?;
+    let Some(index) = (offset + cursor).checked_sub(1) else {
+        return Ok(());
+    };
+
+    results::toggle_mark(lua, buffer, index)?;
+    crate::render(lua).call(())?;
+    select_down(lua, ())
+}
+
+pub fn mark_all(lua: &Lua, _: ()) -> LuaResult<()> {
+    let buffer = Vim::new(lua).nvim_get_current_buf()?;
+    results::mark_all(lua, buffer)?;
+    crate::render(lua).call(())
+}
+
+pub fn unmark_all(lua: &Lua, _: ()) -> LuaResult<()> {
+    let buffer = Vim::new(lua).nvim_get_current_buf()?;
+    results::unmark_all(lua, buffer);
+    crate::render(lua).call(())
+}
+
+/// The marked results, in the order they were marked.
+pub fn marked_values(lua: &Lua) -> LuaResult<Vec<mlua::Value<'_>>> {
+    let buffer = Vim::new(lua).nvim_get_current_buf()?;
+    results::marked_values(lua, buffer)
+}
+
 pub fn origin_window(lua: &Lua, _: ()) -> LuaResult<usize> {
     let buffer = 0;
     let vim = Vim::new(lua);
     vim.nvim_buf_get_var(buffer, "peek_origin_window")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scroll() {
+        assert_eq!(scroll(3, 0, 19), (3, 0));
+        assert_eq!(scroll(20, 0, 19), (19, 1));
+        assert_eq!(scroll(45, 0, 19), (19, 26));
+        assert_eq!(scroll(30, 26, 19), (4, 26));
+        assert_eq!(scroll(5, 26, 19), (1, 4));
+        assert_eq!(scroll(1, 26, 19), (1, 0));
+    }
+}
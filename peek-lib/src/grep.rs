@@ -0,0 +1,165 @@
+use std::fs;
+use std::path::Path;
+
+use mlua::prelude::*;
+use mlua::{FromLua, LuaSerdeExt};
+use serde::{Deserialize, Serialize};
+
+use crate::preview;
+use crate::search::{lowercase, CaseMode};
+
+/// Matching lines are cut down to this many characters for display.
+const MAX_TEXT_CHARS: usize = 300;
+
+/// A line of a file containing the searched text.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Hash)]
+pub struct Hit {
+    pub path: String,
+    pub line: usize,
+    /// The byte column the first match starts at, counting from 1.
+    pub column: usize,
+    pub text: String,
+    /// The character indices of every match in the line rendered by [`Hit::to_line`],
+    /// so they can be highlighted. Named apart from the `positions` that matching the
+    /// prompt adds, which are highlighted along with these.
+    #[serde(default)]
+    pub match_positions: Vec<usize>,
+}
+
+impl<'lua> FromLua<'lua> for Hit {
+    fn from_lua(value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
+        lua.from_value(value)
+    }
+}
+
+impl Hit {
+    pub fn to_line(&self) -> String {
+        format!("{}:{}:{}: {}", self.path, self.line, self.column, self.text)
+    }
+}
+
+/// Literal text to search for, compared case-insensitively unless `case_mode`
+/// says otherwise, just like the terms of a prompt.
+#[derive(Debug, PartialEq)]
+pub struct Pattern {
+    chars: Vec<char>,
+    case_sensitive: bool,
+}
+
+impl Pattern {
+    /// Returns `None` for an empty pattern, which would match everything.
+    pub fn new(pattern: &str, case_mode: CaseMode) -> Option<Pattern> {
+        if pattern.is_empty() {
+            return None;
+        }
+
+        let case_sensitive = match case_mode {
+            CaseMode::Smart => pattern.chars().any(char::is_uppercase),
+            CaseMode::Ignore => false,
+            CaseMode::Respect => true,
+        };
+        Some(Pattern {
+            chars: pattern.chars().map(|c| fold(c, case_sensitive)).collect(),
+            case_sensitive,
+        })
+    }
+
+    /// The character indices at which the pattern starts in `text`, without overlaps.
+    fn find(&self, text: &str) -> Vec<usize> {
+        let text: Vec<char> = text.chars().map(|c| fold(c, self.case_sensitive)).collect();
+        let mut found = vec![];
+        let mut start = 0;
+        while start + self.chars.len() <= text.len() {
+            if text[start..start + self.chars.len()] == self.chars[..] {
+                found.push(start);
+                start += self.chars.len();
+            } else {
+                start += 1;
+            }
+        }
+        found
+    }
+}
+
+fn fold(c: char, case_sensitive: bool) -> char {
+    match case_sensitive {
+        true => c,
+        false => lowercase(c),
+    }
+}
+
+/// Every line of the file at `path`, relative to `root`, that contains `pattern`.
+/// Binary files and files that can't be read have none.
+pub fn search_file(root: &Path, path: &str, pattern: &Pattern) -> Vec<Hit> {
+    let Ok(contents) = fs::read(root.join(path)) else {
+        return vec![];
+    };
+    if preview::is_binary(&contents) {
+        return vec![];
+    }
+
+    String::from_utf8_lossy(&contents)
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let starts = pattern.find(line);
+            let first = *starts.first()?;
+            let column = line.char_indices().nth(first).map(|(byte, _)| byte + 1)?;
+            let mut hit = Hit {
+                path: path.to_string(),
+                line: i + 1,
+                column,
+                text: line.chars().take(MAX_TEXT_CHARS).collect(),
+                match_positions: vec![],
+            };
+
+            let prefix = hit.to_line().chars().count() - hit.text.chars().count();
+            hit.match_positions = starts
+                .into_iter()
+                .flat_map(|start| start..start + pattern.chars.len())
+                .filter(|&i| i < MAX_TEXT_CHARS)
+                .map(|i| prefix + i)
+                .collect();
+            Some(hit)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        assert_eq!(Pattern::new("", CaseMode::Smart), None);
+
+        let smart = Pattern::new("foo", CaseMode::Smart).unwrap();
+        assert_eq!(smart.find("Foo foofoo fo"), vec![0, 4, 7]);
+        let upper = Pattern::new("Foo", CaseMode::Smart).unwrap();
+        assert_eq!(upper.find("Foo foofoo fo"), vec![0]);
+        let respect = Pattern::new("foo", CaseMode::Respect).unwrap();
+        assert_eq!(respect.find("Foo foo"), vec![4]);
+        let overlapping = Pattern::new("aa", CaseMode::Smart).unwrap();
+        assert_eq!(overlapping.find("aaa"), vec![0]);
+    }
+
+    #[test]
+    fn test_search_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/lib.rs"), "use vim;\n\tlet é = vim::new(vim);\nfn main() {}\n").unwrap();
+        fs::write(dir.join("binary"), [b'v', b'i', b'm', 0]).unwrap();
+
+        let pattern = Pattern::new("vim", CaseMode::Smart).unwrap();
+        let hits = search_file(dir, "src/lib.rs", &pattern);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].to_line(), "src/lib.rs:1:5: use vim;");
+        assert_eq!(hits[0].match_positions, vec![20, 21, 22]);
+        assert_eq!((hits[1].line, hits[1].column), (2, 11));
+        assert_eq!(hits[1].match_positions, vec![26, 27, 28, 35, 36, 37]);
+
+        assert_eq!(search_file(dir, "binary", &pattern), vec![]);
+        assert_eq!(search_file(dir, "missing", &pattern), vec![]);
+    }
+}
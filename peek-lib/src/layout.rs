@@ -0,0 +1,248 @@
+use serde::Deserialize;
+
+use crate::vim::WindowOptions;
+
+/// Lines taken up by the status and command lines, which floating windows stay clear of.
+const RESERVED_LINES: usize = 2;
+/// Lines and columns taken up by a border.
+const BORDER_SIZE: usize = 2;
+
+/// Where the picker window is opened.
+#[derive(Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Position {
+    /// A split along the bottom of the editor.
+    #[default]
+    Bottom,
+    /// A floating window in the middle of the editor.
+    Center,
+    /// A floating dropdown just below the cursor.
+    Cursor,
+}
+
+/// The `layout` config option. `width` and `height` up to 1 are ratios of the
+/// editor's size, anything larger is an absolute number of columns or lines.
+/// `border` takes any of `nvim_open_win`'s border styles and only applies to
+/// floating windows, whose border shows `title` or else the picker's title.
+/// Pickers that can preview their entries do so next to the results unless
+/// `preview` is `false`.
+#[derive(Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct Layout {
+    #[serde(default)]
+    pub position: Position,
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+    pub border: Option<String>,
+    pub title: Option<String>,
+    pub preview: Option<bool>,
+}
+
+/// Where a floating layout goes in the editor, borders excluded.
+struct Float {
+    relative: &'static str,
+    row: usize,
+    col: usize,
+    width: usize,
+    height: usize,
+    border: String,
+    padding: usize,
+}
+
+impl Layout {
+    /// The options to open the picker window with in an editor `columns` wide and `lines` high.
+    /// With a `preview` floating layouts leave the right half of their width to it.
+    pub fn window_options(&self, columns: usize, lines: usize, title: &str, preview: bool) -> WindowOptions {
+        if self.position == Position::Bottom {
+            return WindowOptions {
+                split: Some("below".to_string()),
+                win: Some(-1),
+                height: Some(size(self.height, 20.0, lines.saturating_sub(RESERVED_LINES)) as i32),
+                ..Default::default()
+            };
+        }
+
+        let float = self.float(columns, lines);
+        let width = match preview {
+            true => float.width / 2,
+            false => float.width,
+        };
+        float.options(float.col, width.max(1), self.title.as_deref().unwrap_or(title))
+    }
+
+    /// The options to open the preview window with next to the picker's `window`.
+    pub fn preview_options(&self, columns: usize, lines: usize, window: usize) -> WindowOptions {
+        if self.position == Position::Bottom {
+            return WindowOptions {
+                split: Some("right".to_string()),
+                win: Some(window as i32),
+                ..Default::default()
+            };
+        }
+
+        let float = self.float(columns, lines);
+        let offset = float.width / 2 + float.padding;
+        let width = float.width.saturating_sub(offset).max(1);
+        float.options(float.col + offset, width, "Preview")
+    }
+
+    fn float(&self, columns: usize, lines: usize) -> Float {
+        let (default_width, default_height) = match self.position {
+            Position::Cursor => (0.5, 12.0),
+            _ => (0.8, 0.6),
+        };
+
+        let border = self.border.clone().unwrap_or("rounded".to_string());
+        let padding = match border.as_str() {
+            "none" => 0,
+            _ => BORDER_SIZE,
+        };
+        let available_lines = lines.saturating_sub(RESERVED_LINES + padding);
+        let available_columns = columns.saturating_sub(padding);
+        let width = size(self.width, default_width, available_columns);
+        let height = size(self.height, default_height, available_lines);
+
+        let (relative, row, col) = match self.position {
+            Position::Cursor => ("cursor", 1, 0),
+            _ => ("editor", available_lines.saturating_sub(height) / 2, available_columns.saturating_sub(width) / 2),
+        };
+
+        Float {
+            relative,
+            row,
+            col,
+            width,
+            height,
+            border,
+            padding,
+        }
+    }
+}
+
+impl Float {
+    fn options(&self, col: usize, width: usize, title: &str) -> WindowOptions {
+        let title = (self.padding > 0).then(|| format!(" {} ", title));
+        WindowOptions {
+            width: Some(width as i32),
+            height: Some(self.height as i32),
+            relative: Some(self.relative.to_string()),
+            row: Some(self.row as i32),
+            col: Some(col as i32),
+            style: Some("minimal".to_string()),
+            title_pos: title.as_ref().map(|_| "center".to_string()),
+            title,
+            border: Some(self.border.clone()),
+            ..Default::default()
+        }
+    }
+}
+
+fn size(value: Option<f64>, default: f64, available: usize) -> usize {
+    let value = value.unwrap_or(default);
+    let size = match value <= 1.0 {
+        true => (available as f64 * value).round() as usize,
+        false => value as usize,
+    };
+    size.clamp(1, available.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bottom() {
+        let options = Layout::default().window_options(100, 50, "Find File", false);
+        assert_eq!(
+            options,
+            WindowOptions {
+                split: Some("below".to_string()),
+                win: Some(-1),
+                height: Some(20),
+                ..Default::default()
+            }
+        );
+
+        let layout = Layout {
+            height: Some(0.5),
+            ..Default::default()
+        };
+        assert_eq!(layout.window_options(100, 50, "", false).height, Some(24));
+    }
+
+    #[test]
+    fn test_center() {
+        let layout = Layout {
+            position: Position::Center,
+            width: Some(0.5),
+            height: Some(10.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            layout.window_options(102, 50, "Find File", false),
+            WindowOptions {
+                width: Some(50),
+                height: Some(10),
+                relative: Some("editor".to_string()),
+                row: Some(18),
+                col: Some(25),
+                style: Some("minimal".to_string()),
+                border: Some("rounded".to_string()),
+                title: Some(" Find File ".to_string()),
+                title_pos: Some("center".to_string()),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_cursor() {
+        let layout = Layout {
+            position: Position::Cursor,
+            border: Some("none".to_string()),
+            height: Some(100.0),
+            ..Default::default()
+        };
+        let options = layout.window_options(80, 30, "Find File", false);
+        assert_eq!(options.relative.as_deref(), Some("cursor"));
+        assert_eq!((options.row, options.col), (Some(1), Some(0)));
+        assert_eq!((options.width, options.height), (Some(40), Some(28)));
+        assert_eq!(options.title, None);
+    }
+
+    #[test]
+    fn test_tiny_editor() {
+        let layout = Layout {
+            position: Position::Center,
+            ..Default::default()
+        };
+        let options = layout.window_options(1, 3, "Find File", false);
+        assert_eq!((options.row, options.col), (Some(0), Some(0)));
+        assert_eq!((options.width, options.height), (Some(1), Some(1)));
+    }
+
+    #[test]
+    fn test_preview() {
+        let bottom = Layout::default();
+        assert_eq!(bottom.window_options(100, 50, "", true).split.as_deref(), Some("below"));
+        assert_eq!(
+            bottom.preview_options(100, 50, 1001),
+            WindowOptions {
+                split: Some("right".to_string()),
+                win: Some(1001),
+                ..Default::default()
+            }
+        );
+
+        let center = Layout {
+            position: Position::Center,
+            width: Some(0.5),
+            ..Default::default()
+        };
+        let picker = center.window_options(102, 50, "Find File", true);
+        let preview = center.preview_options(102, 50, 1001);
+        assert_eq!((picker.col, picker.width), (Some(25), Some(25)));
+        assert_eq!((preview.col, preview.width), (Some(52), Some(23)));
+        assert_eq!(preview.row, picker.row);
+        assert_eq!(preview.title.as_deref(), Some(" Preview "));
+    }
+}
@@ -1,9 +1,22 @@
+pub mod frecency;
 pub mod functions;
+pub mod grep;
+pub mod layout;
 pub mod picker;
+pub mod preview;
+pub mod quickfix;
+pub mod results;
 pub mod search;
+pub mod sorter;
+pub mod source;
 pub mod vim;
+pub mod walker;
+pub mod worker;
+
+use std::rc::Rc;
 
 use mlua::prelude::*;
+use results::{Results, Values};
 use vim::Vim;
 
 #[derive(serde::Deserialize, Debug)]
@@ -12,6 +25,20 @@ pub struct Config<'a> {
     table: Option<mlua::Table<'a>>,
     pub cwd: Option<String>,
     pub title: Option<String>,
+    #[serde(default)]
+    pub case_mode: search::CaseMode,
+    pub hidden: Option<bool>,
+    pub follow_symlinks: Option<bool>,
+    pub max_depth: Option<usize>,
+    pub exclude: Option<Vec<String>>,
+    pub max_results: Option<usize>,
+    /// One of the built-in sorters. A Lua `sorter` function is used through
+    /// [`Config::sort_results`] instead and leaves this unset.
+    pub sorter: Option<sorter::SorterKind>,
+    #[serde(default)]
+    pub layout: layout::Layout,
+    /// Whether moving the selection past either end of the results continues at the other.
+    pub wrap: Option<bool>,
 }
 
 impl<'a> Config<'a> {
@@ -29,13 +56,48 @@ impl<'a> Config<'a> {
             .call(())
     }
 
-    pub fn filter(&self, prompt: String) -> mlua::prelude::LuaResult<Vec<mlua::Value>> {
-        self.table
+    /// Returns `None` when the filter delivers its results asynchronously
+    /// through [`show_results`] instead.
+    pub fn filter(&self, lua: &'a Lua, prompt: String) -> mlua::prelude::LuaResult<Option<Vec<mlua::Value>>> {
+        let results: Option<Vec<mlua::Value>> = self
+            .table
             .as_ref()
             .unwrap()
             .get::<_, mlua::Function>("filter")
             .unwrap()
-            .call(prompt)
+            .call(prompt)?;
+
+        match results {
+            Some(results) => {
+                let mut results = self.sort_results(lua, results)?;
+                results.truncate(self.max_results.unwrap_or(usize::MAX));
+                Ok(Some(results))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Whether the config orders results with a Lua `sorter(a, b)` function.
+    pub fn has_lua_sorter(&self) -> bool {
+        self.lua_sorter().is_some()
+    }
+
+    fn lua_sorter(&self) -> Option<mlua::Function<'a>> {
+        self.table.as_ref()?.get::<_, Option<mlua::Function>>("sorter").ok()?
+    }
+
+    /// Orders `results` with the config's Lua `sorter` function, which like a
+    /// `table.sort` comparator returns whether its first argument goes first.
+    /// Results are returned unchanged when there is no such function.
+    pub fn sort_results(&self, lua: &'a Lua, results: Vec<mlua::Value<'a>>) -> LuaResult<Vec<mlua::Value<'a>>> {
+        let Some(comparator) = self.lua_sorter() else {
+            return Ok(results);
+        };
+
+        let table = lua.create_sequence_from(results)?;
+        let sort: mlua::Function = lua.globals().get::<_, mlua::Table>("table")?.get("sort")?;
+        sort.call::<_, ()>((table.clone(), comparator))?;
+        table.sequence_values().collect()
     }
 
     pub fn to_line(&self, value: &'a mlua::Value) -> mlua::prelude::LuaResult<String> {
@@ -47,6 +109,43 @@ impl<'a> Config<'a> {
             .call(value)
     }
 
+    /// The quickfix item for `value`. Without a `to_quickfix` function the item
+    /// only has the entry's line as its text.
+    pub fn to_quickfix(&self, value: &'a mlua::Value) -> LuaResult<quickfix::Item> {
+        match self
+            .table
+            .as_ref()
+            .unwrap()
+            .get::<_, Option<mlua::Function>>("to_quickfix")?
+        {
+            Some(to_quickfix) => to_quickfix.call(value),
+            None => Ok(quickfix::Item {
+                text: self.to_line(value)?,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Whether the picker can preview its entries.
+    pub fn has_preview(&self) -> bool {
+        self.table
+            .as_ref()
+            .is_some_and(|t| t.get::<_, Option<mlua::Function>>("preview").is_ok_and(|f| f.is_some()))
+    }
+
+    /// What to show in the preview window for `value`, if anything.
+    pub fn preview(&self, value: mlua::Value<'a>) -> LuaResult<Option<preview::Preview>> {
+        match self
+            .table
+            .as_ref()
+            .unwrap()
+            .get::<_, Option<mlua::Function>>("preview")?
+        {
+            Some(preview) => preview.call(value),
+            None => Ok(None),
+        }
+    }
+
     pub fn on_refresh_callback(&self) -> LuaResult<()> {
         if let Ok(fun) = self.table.as_ref().unwrap().get::<_, mlua::Function>("on_refresh") {
             fun.call::<_, ()>(())?;
@@ -89,6 +188,26 @@ pub fn setup(lua: &Lua, _: ()) -> LuaResult<()> {
             ..Default::default()
         },
     )?;
+
+    vim.nvim_set_hl(
+        0,
+        "PeekMatch",
+        vim::HighlightOptions {
+            fg: Some("orange".into()),
+            bold: Some(true),
+            ..Default::default()
+        },
+    )?;
+
+    vim.nvim_set_hl(
+        0,
+        "PeekMarked",
+        vim::HighlightOptions {
+            fg: Some("green".into()),
+            bold: Some(true),
+            ..Default::default()
+        },
+    )?;
     Ok(())
 }
 
@@ -101,10 +220,14 @@ pub fn create_window(lua: &Lua, config: mlua::Table) -> LuaResult<()> {
     let buffer = vim.nvim_create_buffer(false, true)?;
     let origin_win = vim.nvim_get_current_win().unwrap();
 
-    lua.load("vim.cmd('bot sp')").eval()?;
-    let win = vim.win_get_id()?;
-    vim.nvim_win_set_height(win, 20)?;
-    vim.nvim_win_set_buf(win, buffer)?;
+    let options = Config::from_lua(LuaValue::Table(config.clone()), lua)?;
+    let preview = options.has_preview() && options.layout.preview.unwrap_or(true);
+    let title = options.title.clone().unwrap_or("Peek".to_string());
+    let (columns, lines) = vim.editor_size()?;
+    let win = vim.nvim_open_win(buffer, true, options.layout.window_options(columns, lines, &title, preview))?;
+    if preview {
+        preview::open(lua, buffer, win as usize, &options)?;
+    }
 
     // Window/Buffer config
     lua.load("vim.cmd('startinsert')").eval()?;
@@ -114,7 +237,8 @@ pub fn create_window(lua: &Lua, config: mlua::Table) -> LuaResult<()> {
     lua.load("vim.cmd('set filetype=peek')").eval()?;
     vim.nvim_buf_set_var(buffer, "peek_origin_window", LuaValue::Integer(origin_win.try_into().unwrap()))?;
     vim.nvim_buf_set_var(buffer, "peek_cursor", LuaValue::Integer(0))?;
-    vim.nvim_buf_set_var(buffer, "peek_limit", LuaValue::Integer(20))?;
+    let limit = vim.nvim_win_get_height(win as usize)?;
+    vim.nvim_buf_set_var(buffer, "peek_limit", LuaValue::Integer(limit as i64))?;
     vim.nvim_buf_set_var(buffer, "peek_offset", LuaValue::Integer(0))?;
     vim.nvim_buf_set_var(buffer, "peek_config", LuaValue::Table(config.clone()))?;
 
@@ -127,9 +251,9 @@ pub fn create_window(lua: &Lua, config: mlua::Table) -> LuaResult<()> {
     apply_mappings(lua, buffer, default_mappings(lua));
     apply_mappings(lua, buffer, custom_mappings);
 
-    let initial_data: Vec<mlua::Value> = conf.filter("".to_string())?;
-    vim.nvim_buf_set_var(buffer, "peek_results", lua.to_value(&initial_data).unwrap())?;
+    let initial_data: Vec<mlua::Value> = conf.filter(lua, "".to_string())?.unwrap_or_default();
     vim.nvim_buf_set_var(buffer, "peek_results_count", lua.to_value(&initial_data.len()).unwrap())?;
+    results::set(lua, buffer, Rc::new(Values::new(lua, initial_data)?))?;
     render(lua).call(())?;
 
     let buff_attach_function = lua.create_function(
@@ -143,25 +267,7 @@ pub fn create_window(lua: &Lua, config: mlua::Table) -> LuaResult<()> {
             let lines = vim.nvim_buf_get_lines(buffer, 0, 1, false)?;
             let prompt = lines.first().unwrap().clone();
 
-            let callback = lua.create_function(move |lua, ()| {
-                let config = Config::new(lua);
-                let search_results: Vec<mlua::Value> = config.filter(prompt.clone()).unwrap();
-
-                let vim = Vim::new(lua);
-                vim.nvim_buf_set_var(buffer, "peek_results", lua.to_value(&search_results).unwrap())?;
-                vim.nvim_buf_set_var(buffer, "peek_results_count", lua.to_value(&search_results.len()).unwrap())?;
-                vim.nvim_buf_set_var(buffer, "peek_cursor", LuaValue::Integer(1))?;
-                vim.nvim_buf_set_var(buffer, "peek_offset", LuaValue::Integer(0))?;
-                render(lua).call(())?;
-
-                let namespace = vim.nvim_create_namespace("PeekSelection")?;
-                vim.nvim_buf_clear_namespace(buffer, namespace, 0, -1)?;
-                if !search_results.is_empty() {
-                    highlight_selected_line(&vim, buffer, 1)?;
-                }
-                config.on_refresh_callback()?;
-                Ok(())
-            })?;
+            let callback = lua.create_function(move |lua, ()| update_results(lua, buffer, prompt.clone()))?;
             vim.vim_schedule(callback)?;
 
             Ok(false)
@@ -180,35 +286,149 @@ pub fn create_window(lua: &Lua, config: mlua::Table) -> LuaResult<()> {
     Ok(())
 }
 
+/// Re-runs the picker's filter for `prompt` and redraws the results from the top.
+pub fn update_results(lua: &Lua, buffer: usize, prompt: String) -> LuaResult<()> {
+    let config = Config::new(lua);
+    let search_results = config.filter(lua, prompt)?;
+    match search_results {
+        Some(search_results) => show_results(lua, buffer, Rc::new(Values::new(lua, search_results)?)),
+        None => Ok(()),
+    }
+}
+
+/// Replaces the picker's results and redraws them from the top.
+pub fn show_results(lua: &Lua, buffer: usize, search_results: Rc<dyn Results>) -> LuaResult<()> {
+    let config = Config::new(lua);
+    let vim = Vim::new(lua);
+    let total = search_results.len();
+    results::set(lua, buffer, search_results)?;
+    vim.nvim_buf_set_var(buffer, "peek_results_count", lua.to_value(&total).unwrap())?;
+    vim.nvim_buf_set_var(buffer, "peek_cursor", LuaValue::Integer(1))?;
+    vim.nvim_buf_set_var(buffer, "peek_offset", LuaValue::Integer(0))?;
+    render(lua).call(())?;
+
+    let namespace = vim.nvim_create_namespace("PeekSelection")?;
+    vim.nvim_buf_clear_namespace(buffer, namespace, 0, -1)?;
+    if total > 0 {
+        highlight_selected_line(&vim, buffer, 1)?;
+    }
+    preview::update(lua)?;
+    config.on_refresh_callback()
+}
+
+/// Swaps in a newer result set for the same prompt, e.g. while a streaming source
+/// is still producing, keeping the selection where it is.
+pub fn replace_results(lua: &Lua, buffer: usize, search_results: Rc<dyn Results>) -> LuaResult<()> {
+    let config = Config::new(lua);
+    let vim = Vim::new(lua);
+    let mut cursor: usize = vim.nvim_buf_get_var(buffer, "peek_cursor")?;
+    let offset: usize = vim.nvim_buf_get_var(buffer, "peek_offset")?;
+    let total = search_results.len();
+
+    results::set(lua, buffer, search_results)?;
+    vim.nvim_buf_set_var(buffer, "peek_results_count", lua.to_value(&total).unwrap())?;
+    if cursor == 0 || offset + cursor > total {
+        cursor = total.min(1);
+        vim.nvim_buf_set_var(buffer, "peek_cursor", LuaValue::Integer(cursor as i64))?;
+        vim.nvim_buf_set_var(buffer, "peek_offset", LuaValue::Integer(0))?;
+    }
+    render(lua).call(())?;
+
+    let namespace = vim.nvim_create_namespace("PeekSelection")?;
+    vim.nvim_buf_clear_namespace(buffer, namespace, 0, -1)?;
+    if cursor > 0 {
+        highlight_selected_line(&vim, buffer, cursor as i32)?;
+    }
+    preview::update(lua)?;
+    config.on_refresh_callback()
+}
+
 fn highlight_selected_line(vim: &Vim, buffer: usize, line: i32) -> LuaResult<()> {
     let namespace = vim.nvim_create_namespace("PeekSelection")?;
     let options = crate::vim::ExtMarkOptions {
         hl_group: Some("PeekSelection".to_string()),
         hl_eol: Some(true),
         end_row: Some(line as usize + 1),
+        end_col: None,
+        virt_text: None,
+        virt_text_pos: None,
     };
 
     vim.nvim_buf_clear_namespace(buffer, namespace, 0, -1)?;
     vim.nvim_buf_set_extmark(buffer, namespace, line as usize, 0, options)
 }
 
+fn highlight_matches(vim: &Vim, buffer: usize, values: &[mlua::Value], lines: &[String]) -> LuaResult<()> {
+    let namespace = vim.nvim_create_namespace("PeekMatch")?;
+    vim.nvim_buf_clear_namespace(buffer, namespace, 0, -1)?;
+
+    for (row, (value, line)) in values.iter().zip(lines).enumerate() {
+        // Items that carry matches of their own, like grep hits, keep them under
+        // `match_positions`, next to the `positions` of matching the prompt.
+        let positions: Vec<usize> = match value {
+            LuaValue::Table(t) => {
+                let mut positions = t.get::<_, Option<Vec<usize>>>("positions")?.unwrap_or_default();
+                positions.extend(t.get::<_, Option<Vec<usize>>>("match_positions")?.unwrap_or_default());
+                positions
+            }
+            _ => continue,
+        };
+
+        for (col, c) in line
+            .char_indices()
+            .enumerate()
+            .filter_map(|(i, x)| positions.contains(&i).then_some(x))
+        {
+            let options = crate::vim::ExtMarkOptions {
+                hl_group: Some("PeekMatch".to_string()),
+                hl_eol: None,
+                end_row: None,
+                end_col: Some(col + c.len_utf8()),
+                virt_text: None,
+                virt_text_pos: None,
+            };
+            vim.nvim_buf_set_extmark(buffer, namespace, row + 1, col, options)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn highlight_marks(vim: &Vim, buffer: usize, marked: &[bool]) -> LuaResult<()> {
+    let namespace = vim.nvim_create_namespace("PeekMarked")?;
+    vim.nvim_buf_clear_namespace(buffer, namespace, 0, -1)?;
+
+    for (row, _) in marked.iter().enumerate().filter(|(_, marked)| **marked) {
+        let options = crate::vim::ExtMarkOptions {
+            hl_group: None,
+            hl_eol: None,
+            end_row: None,
+            end_col: None,
+            virt_text: Some(vec![("+ ".to_string(), "PeekMarked".to_string())]),
+            virt_text_pos: Some("inline".to_string()),
+        };
+        vim.nvim_buf_set_extmark(buffer, namespace, row + 1, 0, options)?;
+    }
+
+    Ok(())
+}
+
 pub fn render(lua: &Lua) -> mlua::Function {
     lua.create_function(|lua, ()| {
         let vim = Vim::new(lua);
         let buffer = vim.bufnr()?;
         let config = Config::new(lua);
-        let data: Vec<mlua::Value> = vim.nvim_buf_get_var(buffer, "peek_results")?;
         let limit: usize = vim.nvim_buf_get_var(buffer, "peek_limit")?;
         let offset: usize = vim.nvim_buf_get_var(buffer, "peek_offset")?;
 
-        let lines: Vec<String> = data
-            .iter()
-            .map(|x| config.to_line(x).unwrap())
-            .skip(offset)
-            .take(limit)
-            .collect();
+        let visible = results::values(lua, buffer, offset..offset + limit)?;
+        let lines: Vec<String> = visible.iter().map(|x| config.to_line(x).unwrap()).collect();
+
+        let marked = results::marked(lua, buffer, offset..offset + visible.len())?;
 
-        vim.nvim_buf_set_lines(buffer, 1, -1, false, lines)?;
+        vim.nvim_buf_set_lines(buffer, 1, -1, false, lines.clone())?;
+        highlight_matches(&vim, buffer, &visible, &lines)?;
+        highlight_marks(&vim, buffer, &marked)?;
         Ok(())
     })
     .unwrap()
@@ -250,6 +470,45 @@ pub fn default_mappings(lua: &Lua) -> mlua::Table<'_> {
     insert
         .set("<Up>", lua.create_function(functions::select_up).unwrap())
         .unwrap();
+    insert
+        .set("<C-f>", lua.create_function(functions::page_down).unwrap())
+        .unwrap();
+    insert
+        .set("<PageDown>", lua.create_function(functions::page_down).unwrap())
+        .unwrap();
+    insert
+        .set("<C-b>", lua.create_function(functions::page_up).unwrap())
+        .unwrap();
+    insert
+        .set("<PageUp>", lua.create_function(functions::page_up).unwrap())
+        .unwrap();
+    insert
+        .set("<C-Home>", lua.create_function(functions::select_first).unwrap())
+        .unwrap();
+    insert
+        .set("<C-End>", lua.create_function(functions::select_last).unwrap())
+        .unwrap();
+    insert
+        .set("<Tab>", lua.create_function(functions::toggle_mark).unwrap())
+        .unwrap();
+    insert
+        .set("<M-a>", lua.create_function(functions::mark_all).unwrap())
+        .unwrap();
+    insert
+        .set("<M-d>", lua.create_function(functions::unmark_all).unwrap())
+        .unwrap();
+    insert
+        .set("<C-q>", lua.create_function(quickfix::send_to_quickfix).unwrap())
+        .unwrap();
+    insert
+        .set("<M-q>", lua.create_function(quickfix::send_to_loclist).unwrap())
+        .unwrap();
+    normal
+        .set("gg", lua.create_function(functions::select_first).unwrap())
+        .unwrap();
+    normal
+        .set("G", lua.create_function(functions::select_last).unwrap())
+        .unwrap();
 
     table.set("i", insert).unwrap();
     table.set("n", normal).unwrap();
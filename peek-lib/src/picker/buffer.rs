@@ -1,14 +1,22 @@
+use std::path::{Path, PathBuf};
+
 use mlua::prelude::*;
 use mlua::{FromLua, Lua, LuaSerdeExt};
 use serde::{Deserialize, Serialize};
 
-use crate::vim::{GetOptionValue, Vim};
-use crate::{functions, search, Config};
+use crate::preview::{Preview, Previewer};
+use crate::quickfix::Item;
+use crate::search::Scheme;
+use crate::sorter::{self, SorterKind};
+use crate::source::Source;
+use crate::vim::{GetOptionValue, OpenMode, Vim};
+use crate::worker::Worker;
+use crate::{frecency, functions, Config};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Hash)]
 pub struct Buffer {
-    id: usize,
-    name: String,
+    pub id: usize,
+    pub name: String,
 }
 
 impl<'lua> FromLua<'lua> for Buffer {
@@ -17,27 +25,30 @@ impl<'lua> FromLua<'lua> for Buffer {
     }
 }
 
-pub fn filter(lua: &Lua, prompt: String) -> LuaResult<LuaValue> {
-    let _vim = Vim::new(lua);
-    let listed_buffers = listed_buffers(lua);
-    let buffer_names: String = listed_buffers
-        .iter()
-        .map(|x| x.name.clone())
-        .collect::<Vec<String>>()
-        .join("\n");
-    let matches = search::fzf(prompt, buffer_names.as_bytes().to_vec());
-    let filtered_buffers: Vec<Buffer> = listed_buffers
-        .into_iter()
-        .filter(|x| matches.contains(&x.name))
-        .collect();
+pub fn filter(lua: &Lua, boosts: &frecency::Boosts, worker: &Worker, prompt: String) -> LuaResult<()> {
+    let config = Config::new(lua);
+    let vim = Vim::new(lua);
+    let buffers = listed_buffers(lua, config.cwd.as_deref());
 
-    let result = lua.to_value(&filtered_buffers)?;
-    Ok(result)
+    // The listed buffers can't change while the picker is open, so neither can their boosts.
+    let sorter = sorter::new(config.sorter.unwrap_or(SorterKind::Frecency), || {
+        boosts.get(lua, Path::new(""), |frecency, now| {
+            buffers
+                .iter()
+                .map(|buffer| {
+                    let key = frecency::key(Path::new(&vim.nvim_buf_get_name(buffer.id)?));
+                    Ok((buffer.name.clone(), frecency.boost(&key, now)))
+                })
+                .collect()
+        })
+    })?;
+
+    worker.search(lua, prompt, Scheme::Default, Source::from_vec(buffers), |x| &x.name, sorter)
 }
 
-fn listed_buffers(lua: &Lua) -> Vec<Buffer> {
+/// The listed buffers, named relative to `cwd` if it is given.
+pub fn listed_buffers(lua: &Lua, cwd: Option<&str>) -> Vec<Buffer> {
     let vim = Vim::new(lua);
-    let config = Config::new(lua);
     let buffer_ids = vim.nvim_list_bufs().unwrap();
     buffer_ids
         .into_iter()
@@ -48,7 +59,7 @@ fn listed_buffers(lua: &Lua) -> Vec<Buffer> {
         .map(|id| {
             let name = vim.nvim_buf_get_name(id).unwrap();
 
-            if let Some(cwd) = &config.cwd {
+            if let Some(cwd) = cwd {
                 return Buffer {
                     id,
                     name: name.strip_prefix(cwd).unwrap_or(&name).to_string(),
@@ -64,17 +75,60 @@ pub fn to_line(_lua: &Lua, buffer: Buffer) -> LuaResult<String> {
     Ok(buffer.name)
 }
 
+/// Points at the line the buffer's cursor was last on.
+pub fn to_quickfix(lua: &Lua, buffer: Buffer) -> LuaResult<Item> {
+    let (line, column) = Vim::new(lua).nvim_buf_get_mark(buffer.id, "\"")?;
+    Ok(Item {
+        bufnr: Some(buffer.id),
+        lnum: Some(line.max(1)),
+        col: Some(column + 1),
+        text: buffer.name,
+        ..Default::default()
+    })
+}
+
+/// Previews the selected buffer around where its cursor last was.
+pub struct BufferPreviewer;
+
+impl Previewer for BufferPreviewer {
+    fn preview<'lua>(&self, lua: &'lua Lua, value: LuaValue<'lua>) -> LuaResult<Option<Preview>> {
+        let buffer: Buffer = lua.from_value(value)?;
+        let (line, _) = Vim::new(lua).nvim_buf_get_mark(buffer.id, "\"")?;
+        Ok(Some(Preview::Buffer {
+            buffer: buffer.id,
+            line: Some(line),
+        }))
+    }
+}
+
 pub fn open_buffer(lua: &Lua, _: ()) -> LuaResult<()> {
-    let selected: Option<mlua::Value> = functions::selected_value(lua, ())?;
+    open(lua, OpenMode::Edit)
+}
 
-    if let Some(selected_buffer) = selected {
-        let buf: Buffer = lua.from_value(selected_buffer)?;
-        let vim = Vim::new(lua);
-        let origin_window: usize = functions::origin_window(lua, ())?;
+/// Shows the marked buffers, or the selected one, from the origin window as
+/// `mode` says. Splits and tabs get one per buffer.
+pub fn open(lua: &Lua, mode: OpenMode) -> LuaResult<()> {
+    let selected: Vec<mlua::Value> = functions::selected_values(lua, ())?;
+    if selected.is_empty() {
+        return Ok(());
+    }
+
+    let vim = Vim::new(lua);
+    let mut buffers = vec![];
+    let mut paths = vec![];
+    for value in selected {
+        let buf: Buffer = lua.from_value(value)?;
+        let name = vim.nvim_buf_get_name(buf.id)?;
+        if !name.is_empty() {
+            paths.push(PathBuf::from(name));
+        }
+        buffers.push(buf.id);
+    }
+    frecency::record_all(lua, &paths)?;
 
-        functions::exit(lua, ())?;
-        vim.nvim_win_set_buf(origin_window, buf.id)?;
-        vim.nvim_set_current_win(origin_window)?;
+    functions::exit(lua, ())?;
+    for buffer in buffers {
+        vim.open_buffer(buffer, mode)?;
     }
     Ok(())
 }
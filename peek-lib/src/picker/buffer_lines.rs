@@ -0,0 +1,168 @@
+use std::sync::Arc;
+
+use mlua::prelude::*;
+use mlua::{FromLua, Lua, LuaSerdeExt};
+use serde::{Deserialize, Serialize};
+
+use crate::picker::buffer;
+use crate::preview::{Preview, Previewer};
+use crate::quickfix::Item;
+use crate::search::Scheme;
+use crate::sorter::{self, SorterKind};
+use crate::source::Source;
+use crate::vim::{OpenMode, Vim};
+use crate::worker::Worker;
+use crate::{functions, Config};
+
+/// A line of a buffer, shown and matched against the prompt as `label`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Hash)]
+pub struct Line {
+    pub buffer: usize,
+    pub line: usize,
+    pub text: String,
+    pub label: String,
+}
+
+impl<'lua> FromLua<'lua> for Line {
+    fn from_lua(value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
+        lua.from_value(value)
+    }
+}
+
+/// Every line of `buffer`, labelled with its line number padded to line up.
+pub fn lines(lua: &Lua, buffer: usize) -> LuaResult<Vec<Line>> {
+    let lines = Vim::new(lua).nvim_buf_get_lines(buffer, 0, -1, false)?;
+    let width = lines.len().to_string().len();
+    Ok(to_lines(buffer, lines, |line, text| label(width, line, text)))
+}
+
+/// Every line of every listed buffer, labelled as `name:line: text` with names relative to `cwd` if it is given.
+pub fn all_lines(lua: &Lua, cwd: Option<&str>) -> LuaResult<Vec<Line>> {
+    let vim = Vim::new(lua);
+    let mut lines = vec![];
+    for buffer in buffer::listed_buffers(lua, cwd) {
+        let name = match buffer.name.is_empty() {
+            true => "[No Name]".to_string(),
+            false => buffer.name,
+        };
+        let text = vim.nvim_buf_get_lines(buffer.id, 0, -1, false)?;
+        lines.extend(to_lines(buffer.id, text, |line, text| format!("{}:{}: {}", name, line, text)));
+    }
+    Ok(lines)
+}
+
+fn to_lines(buffer: usize, lines: Vec<String>, label: impl Fn(usize, &str) -> String) -> Vec<Line> {
+    lines
+        .into_iter()
+        .enumerate()
+        .map(|(i, text)| Line {
+            buffer,
+            line: i + 1,
+            label: label(i + 1, &text),
+            text,
+        })
+        .collect()
+}
+
+/// `line` right-aligned to `width` digits, followed by its `text`.
+fn label(width: usize, line: usize, text: &str) -> String {
+    format!("{:>width$} {}", line, text)
+}
+
+pub fn filter(lua: &Lua, source: &Source<Line>, worker: &Worker, prompt: String) -> LuaResult<()> {
+    let config = Config::new(lua);
+    // Lines have no path for frecency to go on.
+    let sorter = sorter::new(config.sorter.unwrap_or(SorterKind::Fuzzy), || Ok(Arc::default()))?;
+    worker.search(lua, prompt, Scheme::Default, source.clone(), |x| &x.label, sorter)
+}
+
+pub fn to_line(_lua: &Lua, line: Line) -> LuaResult<String> {
+    Ok(line.label)
+}
+
+pub fn to_quickfix(_lua: &Lua, line: Line) -> LuaResult<Item> {
+    Ok(Item {
+        bufnr: Some(line.buffer),
+        lnum: Some(line.line),
+        text: line.text,
+        ..Default::default()
+    })
+}
+
+/// Closes the picker, leaving the origin window's cursor on the selected line
+/// in the middle of the window.
+pub fn jump(lua: &Lua, _: ()) -> LuaResult<()> {
+    let Some(value) = functions::selected_value(lua, ())? else {
+        return Ok(());
+    };
+    let line: Line = lua.from_value(value)?;
+    let vim = Vim::new(lua);
+    let origin_window = functions::origin_window(lua, ())?;
+
+    functions::exit(lua, ())?;
+    vim.nvim_win_set_cursor(origin_window, vec![line.line as i32, 0])?;
+    let center = lua.create_function(|lua, ()| lua.load("vim.cmd('normal! zz')").exec())?;
+    vim.nvim_win_call(origin_window, center)
+}
+
+pub fn open_line(lua: &Lua, _: ()) -> LuaResult<()> {
+    open(lua, OpenMode::Edit)
+}
+
+/// Shows the buffers of the marked lines, or of the selected one, from the origin
+/// window as `mode` says, with the cursor on that line. Splits and tabs get one per line.
+pub fn open(lua: &Lua, mode: OpenMode) -> LuaResult<()> {
+    let lines = functions::selected_values(lua, ())?
+        .into_iter()
+        .map(|value| lua.from_value(value))
+        .collect::<LuaResult<Vec<Line>>>()?;
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    let vim = Vim::new(lua);
+    functions::exit(lua, ())?;
+    for line in lines {
+        vim.open_buffer(line.buffer, mode)?;
+        vim.nvim_win_set_cursor(0, vec![line.line as i32, 0])?;
+    }
+    Ok(())
+}
+
+/// Previews the buffer around the selected line.
+pub struct LinePreviewer;
+
+impl Previewer for LinePreviewer {
+    fn preview<'lua>(&self, lua: &'lua Lua, value: LuaValue<'lua>) -> LuaResult<Option<Preview>> {
+        let line: Line = lua.from_value(value)?;
+        Ok(Some(Preview::Buffer {
+            buffer: line.buffer,
+            line: Some(line.line),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_lines() {
+        let lines = to_lines(3, vec!["a".to_string(), "b".to_string()], |line, text| format!("x:{}: {}", line, text));
+        assert_eq!(
+            lines.last(),
+            Some(&Line {
+                buffer: 3,
+                line: 2,
+                text: "b".to_string(),
+                label: "x:2: b".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_label() {
+        assert_eq!(label(3, 7, "fn main() {}"), "  7 fn main() {}");
+        assert_eq!(label(3, 120, ""), "120 ");
+    }
+}
@@ -1,13 +1,23 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
 use mlua::prelude::*;
 use mlua::{FromLua, Lua, LuaSerdeExt};
 use serde::{Deserialize, Serialize};
 
-use crate::functions;
-use crate::vim::Vim;
+use crate::preview::{Preview, Previewer};
+use crate::quickfix::Item;
+use crate::search::Scheme;
+use crate::sorter::{self, SorterKind};
+use crate::source::Source;
+use crate::vim::{OpenMode, Vim};
+use crate::worker::Worker;
+use crate::{frecency, functions, walker, Config};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Hash)]
 pub struct File {
-    path: String,
+    pub path: String,
 }
 
 impl<'lua> FromLua<'lua> for File {
@@ -16,47 +26,105 @@ impl<'lua> FromLua<'lua> for File {
     }
 }
 
-pub fn filter(lua: &Lua, prompt: String) -> LuaResult<LuaValue> {
-    let mut binding = std::process::Command::new("fd");
-    let cmd = binding
-        .arg("-t")
-        .arg("file")
-        .arg("-H")
-        .arg("-E")
-        .arg(".git")
-        .output()
-        .unwrap()
-        .stdout;
-    let fzf_output = crate::search::fzf(prompt, cmd);
-
-    let search_results: Vec<File> = fzf_output
-        .iter()
-        .take(500)
-        .map(|x| File { path: x.to_owned() })
-        .collect();
-    let result = lua.to_value(&search_results)?;
-    Ok(result)
+const WALK_BATCH_SIZE: usize = 1000;
+
+/// The files found under the picker's root. They are streamed in the background
+/// when the picker opens and then reused for every following keystroke until refreshed.
+#[derive(Clone, Default)]
+pub struct FileCache(Rc<RefCell<Option<Source<File>>>>);
+
+pub fn filter(
+    lua: &Lua, cache: &FileCache, boosts: &frecency::Boosts, worker: &Worker, prompt: String,
+) -> LuaResult<()> {
+    let config = Config::new(lua);
+    if cache.0.borrow().is_none() {
+        let root = root(&config);
+        let options = walker::WalkOptions::from_config(&config);
+        let source = Source::spawn(move |sink| {
+            // Walking only fails on an `exclude` glob that doesn't parse. This thread
+            // has no way to report it, so the error is ignored and nothing is listed.
+            let _ = walker::walk_batched(&root, &options, WALK_BATCH_SIZE, |batch| {
+                sink.send(batch.into_iter().map(|path| File { path }).collect())
+            });
+        });
+        cache.0.replace(Some(source));
+    }
+
+    let source = cache.0.borrow().clone().unwrap();
+    let sorter = sorter::new(config.sorter.unwrap_or(SorterKind::Frecency), || boosts.below(lua, &root(&config)))?;
+    worker.search(lua, prompt, Scheme::Path, source, |x| &x.path, sorter)
+}
+
+/// Rescans the file system and filters the fresh list with the current prompt.
+pub fn refresh(lua: &Lua, cache: &FileCache) -> LuaResult<()> {
+    let vim = Vim::new(lua);
+    let buffer = vim.nvim_get_current_buf()?;
+    let lines = vim.nvim_buf_get_lines(buffer, 0, 1, false)?;
+    let prompt = lines.first().cloned().unwrap_or_default();
+
+    cache.0.replace(None);
+    crate::update_results(lua, buffer, prompt)
+}
+
+/// The directory the picker's paths are relative to.
+pub(crate) fn root(config: &Config) -> PathBuf {
+    PathBuf::from(config.cwd.as_deref().unwrap_or("."))
 }
 
 pub fn to_line(_lua: &Lua, data: File) -> LuaResult<String> {
     Ok(data.path)
 }
 
+pub fn to_quickfix(lua: &Lua, data: File) -> LuaResult<Item> {
+    let path = root(&Config::new(lua)).join(&data.path);
+    Ok(Item {
+        filename: Some(path.to_string_lossy().into_owned()),
+        lnum: Some(1),
+        text: data.path,
+        ..Default::default()
+    })
+}
+
+/// Previews the start of the selected file.
+pub struct FilePreviewer;
+
+impl Previewer for FilePreviewer {
+    fn preview<'lua>(&self, lua: &'lua Lua, value: LuaValue<'lua>) -> LuaResult<Option<Preview>> {
+        let file: File = lua.from_value(value)?;
+        let path = root(&Config::new(lua)).join(file.path);
+        Ok(path.to_str().map(|path| Preview::File {
+            path: path.to_string(),
+            line: None,
+        }))
+    }
+}
+
+/// Opens the marked files, or the selected one, in the origin window. With
+/// several files the last one ends up shown and the others are loaded as buffers.
 pub fn open_file(lua: &Lua, _: ()) -> LuaResult<()> {
-    let selected: Option<mlua::Value> = functions::selected_value(lua, ())?;
-
-    if let Some(f) = selected {
-        let file: File = lua.from_value(f)?;
-        let vim = Vim::new(lua);
-        let origin_window: usize = functions::origin_window(lua, ())?;
-        let inner_func = lua.create_function(move |lua, ()| {
-            let vim = Vim::new(lua);
-            vim.edit_file(file.path.as_str()).ok();
-            Ok(())
-        })?;
-        functions::exit(lua, ())?;
-        vim.nvim_win_call(origin_window, inner_func)?;
-        vim.nvim_set_current_win(origin_window)?;
+    open(lua, OpenMode::Edit)
+}
+
+/// Opens the marked files, or the selected one, from the origin window as `mode`
+/// says. Splits and tabs get one per file.
+pub fn open(lua: &Lua, mode: OpenMode) -> LuaResult<()> {
+    let selected: Vec<mlua::Value> = functions::selected_values(lua, ())?;
+    if selected.is_empty() {
+        return Ok(());
+    }
+
+    let root = root(&Config::new(lua));
+    let mut paths = vec![];
+    for value in selected {
+        let file: File = lua.from_value(value)?;
+        paths.push(root.join(file.path));
+    }
+    frecency::record_all(lua, &paths)?;
+
+    let vim = Vim::new(lua);
+    functions::exit(lua, ())?;
+    for path in paths.iter() {
+        vim.open_file(path.to_str().unwrap(), mode).ok();
     }
     Ok(())
 }
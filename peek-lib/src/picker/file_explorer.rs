@@ -5,10 +5,16 @@ use mlua::prelude::*;
 use mlua::{FromLua, Lua, LuaSerdeExt};
 use serde::{Deserialize, Serialize};
 
-use crate::functions;
-use crate::vim::Vim;
-
-#[derive(Serialize, Deserialize, Debug)]
+use crate::preview::{Preview, Previewer};
+use crate::quickfix::Item;
+use crate::search::Scheme;
+use crate::sorter::{self, SorterKind};
+use crate::source::Source;
+use crate::vim::{OpenMode, Vim};
+use crate::worker::Worker;
+use crate::{frecency, functions, Config};
+
+#[derive(Serialize, Deserialize, Debug, Hash)]
 pub struct File {
     path: String,
     full_path: String,
@@ -21,15 +27,17 @@ impl<'lua> FromLua<'lua> for File {
     }
 }
 
-pub fn filter(lua: &Lua, prompt: String) -> LuaResult<LuaValue> {
+pub fn filter(lua: &Lua, boosts: &frecency::Boosts, worker: &Worker, prompt: String) -> LuaResult<()> {
+    let config = Config::new(lua);
     let mut x = prompt.clone();
     x.push(' ');
     let path = Path::new(&x).with_file_name("").as_path().to_owned();
     let query = prompt.split('/').last().unwrap_or("").to_string();
+    let sorter = sorter::new(config.sorter.unwrap_or(SorterKind::Fuzzy), || boosts.below(lua, &path))?;
 
     if read_dir(&path).is_err() {
-        let result: Vec<File> = vec![];
-        return lua.to_value(&result);
+        let source = Source::<File>::from_vec(vec![]);
+        return worker.search(lua, query, Scheme::Path, source, |x| &x.path, sorter);
     }
 
     let entries: Vec<File> = read_dir(&path)
@@ -45,22 +53,9 @@ pub fn filter(lua: &Lua, prompt: String) -> LuaResult<LuaValue> {
                 is_dir: meta.is_dir(),
             }
         })
-        .take(500)
         .collect();
 
-    let results = entries
-        .iter()
-        .map(|x| x.path.to_owned())
-        .collect::<Vec<String>>()
-        .join("\n")
-        .as_bytes()
-        .to_vec();
-
-    let matches = crate::search::fzf(query, results);
-    let filtered: Vec<File> = entries.into_iter().filter(|x| matches.contains(&x.path)).collect();
-
-    let result = lua.to_value(&filtered)?;
-    Ok(result)
+    worker.search(lua, query, Scheme::Path, Source::from_vec(entries), |x| &x.path, sorter)
 }
 
 pub fn to_line(_lua: &Lua, data: File) -> LuaResult<String> {
@@ -70,8 +65,74 @@ pub fn to_line(_lua: &Lua, data: File) -> LuaResult<String> {
     Ok(data.path)
 }
 
+pub fn to_quickfix(_lua: &Lua, data: File) -> LuaResult<Item> {
+    Ok(Item {
+        filename: Some(data.full_path),
+        lnum: Some(1),
+        text: data.path,
+        ..Default::default()
+    })
+}
+
+/// Previews the selected file, or lists the contents of the selected directory.
+pub struct ExplorerPreviewer;
+
+impl Previewer for ExplorerPreviewer {
+    fn preview<'lua>(&self, lua: &'lua Lua, value: LuaValue<'lua>) -> LuaResult<Option<Preview>> {
+        let file: File = lua.from_value(value)?;
+        if !file.is_dir {
+            return Ok(Some(Preview::File {
+                path: file.full_path,
+                line: None,
+            }));
+        }
+
+        let mut lines: Vec<String> = read_dir(&file.full_path)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .map(|entry| {
+                        let name = entry.file_name().to_string_lossy().into_owned();
+                        match entry.file_type().is_ok_and(|t| t.is_dir()) {
+                            true => format!("{}/", name),
+                            false => name,
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        lines.sort();
+
+        Ok(Some(Preview::Lines { lines, filetype: None }))
+    }
+}
+
 pub fn select_option(lua: &Lua, _: ()) -> LuaResult<()> {
+    open(lua, OpenMode::Edit)
+}
+
+/// Opens the selected file from the origin window as `mode` says, or enters
+/// the selected directory. With marked entries their files are opened instead,
+/// leaving out directories.
+pub fn open(lua: &Lua, mode: OpenMode) -> LuaResult<()> {
     let vim = Vim::new(lua);
+    let marked = functions::marked_values(lua)?;
+    if !marked.is_empty() {
+        let mut paths = vec![];
+        for value in marked {
+            let file: File = lua.from_value(value)?;
+            if Path::new(&file.full_path).is_file() {
+                paths.push(file.full_path);
+            }
+        }
+
+        functions::exit(lua, ())?;
+        for path in paths {
+            vim.open_file(&path, mode).ok();
+        }
+        return Ok(());
+    }
+
     let selected: Option<mlua::Value> = functions::selected_value(lua, ())?;
 
     if let Some(selected_buffer) = selected {
@@ -85,13 +146,13 @@ pub fn select_option(lua: &Lua, _: ()) -> LuaResult<()> {
             }
 
             if m.is_file() {
-                open_file(lua, file.full_path)?;
+                open_file(lua, file.full_path, mode)?;
             }
         }
     } else {
         let lines = vim.nvim_buf_get_lines(0, 0, 1, false)?;
         let prompt = lines.first().unwrap();
-        open_file(lua, prompt.to_string())?;
+        open_file(lua, prompt.to_string(), mode)?;
     }
 
     Ok(())
@@ -115,17 +176,10 @@ pub fn tab(lua: &Lua, _: ()) -> LuaResult<()> {
     Ok(())
 }
 
-fn open_file(lua: &Lua, path: String) -> LuaResult<()> {
-    let vim = Vim::new(lua);
-    let origin_window: usize = functions::origin_window(lua, ())?;
-    let inner_func = lua.create_function(move |lua, ()| {
-        let vim = Vim::new(lua);
-        vim.edit_file(path.as_str()).ok();
-        Ok(())
-    })?;
+fn open_file(lua: &Lua, path: String, mode: OpenMode) -> LuaResult<()> {
     functions::exit(lua, ())?;
-    vim.nvim_win_call(origin_window, inner_func)?;
-    vim.nvim_set_current_win(origin_window)
+    Vim::new(lua).open_file(path.as_str(), mode).ok();
+    Ok(())
 }
 
 pub fn backspace(lua: &Lua, _: ()) -> LuaResult<()> {
@@ -0,0 +1,131 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use mlua::prelude::*;
+use mlua::Lua;
+use serde::Serialize;
+
+use crate::grep::{Hit, Pattern};
+use crate::picker::live_grep;
+use crate::search::{CaseMode, Scheme};
+use crate::sorter::{self, SorterKind};
+use crate::source::Source;
+use crate::vim::Vim;
+use crate::worker::Worker;
+use crate::Config;
+
+/// The text searched for and the hits found for it. The files are searched
+/// once when the picker opens, and the prompt narrows down those hits.
+#[derive(Clone)]
+pub struct Search {
+    text: String,
+    hits: Rc<RefCell<Option<Source<Labeled>>>>,
+}
+
+impl Search {
+    pub fn new(text: String) -> Search {
+        Search {
+            text,
+            hits: Rc::default(),
+        }
+    }
+}
+
+/// A hit along with the line it is shown as, which the prompt is matched against.
+#[derive(Serialize, Hash)]
+pub struct Labeled {
+    #[serde(flatten)]
+    hit: Hit,
+    #[serde(skip)]
+    label: String,
+}
+
+pub fn filter(lua: &Lua, search: &Search, worker: &Worker, prompt: String) -> LuaResult<()> {
+    let config = Config::new(lua);
+    if search.hits.borrow().is_none() {
+        let source = match Pattern::new(&search.text, CaseMode::Respect) {
+            Some(pattern) => live_grep::search(&config, pattern, |hit| Labeled {
+                label: hit.to_line(),
+                hit,
+            }),
+            None => Source::from_vec(vec![]),
+        };
+        search.hits.replace(Some(source));
+    }
+
+    let source = search.hits.borrow().clone().unwrap();
+    // Hits are single lines, which frecency knows nothing about.
+    let sorter = sorter::new(config.sorter.unwrap_or(SorterKind::Fuzzy), || Ok(Arc::default()))?;
+    worker.search(lua, prompt, Scheme::Default, source, |x| &x.label, sorter)
+}
+
+/// The visually selected text if the current window is in visual mode, or else
+/// the word under the cursor. See [`selection`] for how selections are read.
+pub fn text_under_cursor(lua: &Lua) -> LuaResult<String> {
+    let vim = Vim::new(lua);
+    let mode = vim.nvim_get_mode()?;
+    if !matches!(mode.as_str(), "v" | "V" | "\u{16}") {
+        return vim.expand("<cword>");
+    }
+
+    let start = vim.getpos("v")?;
+    let end = vim.getpos(".")?;
+    lua.load("vim.cmd('normal! ' .. vim.api.nvim_replace_termcodes('<Esc>', true, false, true))")
+        .exec()?;
+
+    let first_line = start.0.min(end.0) as i32;
+    let lines = vim.nvim_buf_get_lines(0, first_line - 1, first_line, false)?;
+    Ok(selection(&mode, start, end, lines.first().map(String::as_str).unwrap_or_default()))
+}
+
+/// The text selected in visual `mode` between the `(line, byte column)` positions
+/// `start` and `end`, given the first selected `line`. Hits never span lines, so
+/// neither does the selection:
+///
+/// - charwise selections over several lines run from where they start to the end of that line,
+/// - blockwise selections take the block's columns of its first line,
+/// - linewise selections take their first line without surrounding whitespace.
+fn selection(mode: &str, start: (usize, usize), end: (usize, usize), line: &str) -> String {
+    let (first, last) = (start.min(end), start.max(end));
+    match mode {
+        "v" if first.0 == last.0 => selected(line, first.1, last.1),
+        "v" => selected(line, first.1, usize::MAX),
+        "\u{16}" => selected(line, start.1.min(end.1), start.1.max(end.1)),
+        _ => line.trim().to_string(),
+    }
+}
+
+/// The part of `line` between the byte columns `start` and `end`, counting from
+/// 1 and including the whole character at `end`.
+fn selected(line: &str, start: usize, end: usize) -> String {
+    let start = start.saturating_sub(1);
+    let end = line
+        .char_indices()
+        .find(|(i, _)| *i >= end.saturating_sub(1))
+        .map(|(i, c)| i + c.len_utf8())
+        .unwrap_or(line.len());
+    line.get(start..end).unwrap_or_default().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selection() {
+        let line = "    let vim = Vim::new(lua);";
+        assert_eq!(selection("v", (3, 13), (3, 9), line), "vim =");
+        assert_eq!(selection("v", (4, 2), (3, 9), line), "vim = Vim::new(lua);");
+        assert_eq!(selection("\u{16}", (3, 11), (5, 9), line), "vim");
+        assert_eq!(selection("V", (3, 1), (3, 1), line), "let vim = Vim::new(lua);");
+    }
+
+    #[test]
+    fn test_selected() {
+        assert_eq!(selected("let vim = Vim::new(lua);", 5, 7), "vim");
+        assert_eq!(selected("let é = 1;", 5, 5), "é");
+        assert_eq!(selected("short", 3, 100), "ort");
+        assert_eq!(selected("", 1, 1), "");
+    }
+}
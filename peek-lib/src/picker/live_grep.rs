@@ -0,0 +1,122 @@
+use std::path::{Path, PathBuf};
+
+use mlua::prelude::*;
+use mlua::{Lua, LuaSerdeExt};
+
+use crate::grep::{self, Hit, Pattern};
+use crate::picker::file::root;
+use crate::preview::{Preview, Previewer};
+use crate::quickfix::Item;
+use crate::search::Scheme;
+use crate::sorter::{self, SorterKind};
+use crate::source::Source;
+use crate::vim::{OpenMode, Vim};
+use crate::worker::Worker;
+use crate::{frecency, functions, walker, Config};
+
+/// Files are searched in batches this big between checks for a newer prompt.
+const WALK_BATCH_SIZE: usize = 100;
+
+/// Searches the contents of the files under the picker's root for the prompt.
+/// Every prompt starts a new search, and the worker cancels the previous one
+/// as it moves on to the new source. Closing the picker cancels the last one.
+pub fn filter(lua: &Lua, boosts: &frecency::Boosts, worker: &Worker, prompt: String) -> LuaResult<()> {
+    let config = Config::new(lua);
+    let sorter = sorter::new(config.sorter.unwrap_or(SorterKind::Fuzzy), || boosts.below(lua, &root(&config)))?;
+
+    let source = match Pattern::new(&prompt, config.case_mode) {
+        Some(pattern) => search(&config, pattern, |hit| hit),
+        None => Source::from_vec(vec![]),
+    };
+
+    // The hits already are what the prompt asked for, so the worker only has to rank them.
+    worker.search(lua, String::new(), Scheme::Default, source, |x: &Hit| &x.path, sorter)
+}
+
+/// Searches the files under the picker's root for `pattern` in the background,
+/// turning every hit into a candidate with `item`.
+pub fn search<T>(config: &Config, pattern: Pattern, item: fn(Hit) -> T) -> Source<T>
+where
+    T: Send + Sync + 'static,
+{
+    let root = root(config);
+    let options = walker::WalkOptions::from_config(config);
+    Source::spawn(move |sink| {
+        // Walking only fails on an `exclude` glob that doesn't parse. This thread
+        // has no way to report it, so the error is ignored and nothing is found.
+        let _ = walker::walk_batched(&root, &options, WALK_BATCH_SIZE, |batch| {
+            sink.send(
+                batch
+                    .iter()
+                    .flat_map(|path| grep::search_file(&root, path, &pattern))
+                    .map(item)
+                    .collect(),
+            )
+        });
+    })
+}
+
+pub fn to_line(_lua: &Lua, hit: Hit) -> LuaResult<String> {
+    Ok(hit.to_line())
+}
+
+pub fn to_quickfix(lua: &Lua, hit: Hit) -> LuaResult<Item> {
+    let path = root(&Config::new(lua)).join(&hit.path);
+    Ok(Item {
+        filename: Some(path.to_string_lossy().into_owned()),
+        lnum: Some(hit.line),
+        col: Some(hit.column),
+        text: hit.text,
+        ..Default::default()
+    })
+}
+
+/// Previews the file around the selected line.
+pub struct GrepPreviewer;
+
+impl Previewer for GrepPreviewer {
+    fn preview<'lua>(&self, lua: &'lua Lua, value: LuaValue<'lua>) -> LuaResult<Option<Preview>> {
+        let hit: Hit = lua.from_value(value)?;
+        let path = root(&Config::new(lua)).join(hit.path);
+        Ok(path.to_str().map(|path| Preview::File {
+            path: path.to_string(),
+            line: Some(hit.line),
+        }))
+    }
+}
+
+pub fn open_hit(lua: &Lua, _: ()) -> LuaResult<()> {
+    open(lua, OpenMode::Edit)
+}
+
+/// Opens the files of the marked hits, or of the selected one, from the origin
+/// window as `mode` says, with the cursor on the start of the match. Splits and
+/// tabs get one per hit.
+pub fn open(lua: &Lua, mode: OpenMode) -> LuaResult<()> {
+    let selected: Vec<mlua::Value> = functions::selected_values(lua, ())?;
+    if selected.is_empty() {
+        return Ok(());
+    }
+
+    let root = root(&Config::new(lua));
+    let mut hits = vec![];
+    for value in selected {
+        let hit: Hit = lua.from_value(value)?;
+        hits.push((root.join(&hit.path), hit));
+    }
+    let paths: Vec<PathBuf> = hits.iter().map(|(path, _)| path.clone()).collect();
+    frecency::record_all(lua, &paths)?;
+
+    functions::exit(lua, ())?;
+    for (path, hit) in hits {
+        jump(lua, &path, hit.line, hit.column, mode)?;
+    }
+    Ok(())
+}
+
+/// Opens `path` as `mode` says and puts the cursor on `line` and byte `column`, both counting from 1.
+pub fn jump(lua: &Lua, path: &Path, line: usize, column: usize, mode: OpenMode) -> LuaResult<()> {
+    let vim = Vim::new(lua);
+    vim.open_file(path.to_str().unwrap(), mode)?;
+    vim.nvim_win_set_cursor(0, vec![line as i32, column.saturating_sub(1) as i32])
+}
@@ -1,19 +1,41 @@
 pub mod buffer;
+pub mod buffer_lines;
 pub mod file;
 pub mod file_explorer;
+pub mod grep_string;
+pub mod live_grep;
+pub mod oldfiles;
 
 use crate::create_window;
+use crate::frecency;
+use crate::functions;
 use crate::picker;
+use crate::preview::{self, Previewer};
+use crate::source::Source;
+use crate::vim::{OpenMode, Vim};
+use crate::worker::Worker;
 use mlua::prelude::*;
 
 pub fn file_picker(lua: &Lua, config: mlua::Table) -> LuaResult<()> {
+    let cache = picker::file::FileCache::default();
+    let filter_cache = cache.clone();
+    let boosts = frecency::Boosts::default();
+    let worker = Worker::default();
+
     let mappings = lua.create_table()?;
     let insert = lua.create_table()?;
     insert.set("<CR>", lua.create_function(picker::file::open_file)?)?;
+    set_open_mappings(&insert, lua, picker::file::open)?;
+    insert.set("<C-r>", lua.create_function(move |lua, ()| picker::file::refresh(lua, &cache))?)?;
     mappings.set("i", insert)?;
 
-    config.set("filter", lua.create_function(picker::file::filter)?)?;
+    config.set(
+        "filter",
+        lua.create_function(move |lua, prompt| picker::file::filter(lua, &filter_cache, &boosts, &worker, prompt))?,
+    )?;
     config.set("to_line", lua.create_function(picker::file::to_line)?)?;
+    config.set("to_quickfix", lua.create_function(picker::file::to_quickfix)?)?;
+    set_previewer(&config, lua, picker::file::FilePreviewer)?;
     config.set("mappings", mappings)?;
     config.set("title", "Find File")?;
     create_window(lua, config)
@@ -23,10 +45,18 @@ pub fn buffer_picker(lua: &Lua, config: mlua::Table) -> LuaResult<()> {
     let mappings = lua.create_table()?;
     let insert = lua.create_table()?;
     insert.set("<CR>", lua.create_function(picker::buffer::open_buffer)?)?;
+    set_open_mappings(&insert, lua, picker::buffer::open)?;
     mappings.set("i", insert)?;
 
-    config.set("filter", lua.create_function(picker::buffer::filter)?)?;
+    let boosts = frecency::Boosts::default();
+    let worker = Worker::default();
+    config.set(
+        "filter",
+        lua.create_function(move |lua, prompt| picker::buffer::filter(lua, &boosts, &worker, prompt))?,
+    )?;
     config.set("to_line", lua.create_function(picker::buffer::to_line)?)?;
+    config.set("to_quickfix", lua.create_function(picker::buffer::to_quickfix)?)?;
+    set_previewer(&config, lua, picker::buffer::BufferPreviewer)?;
     config.set("mappings", mappings)?;
     config.set("title", "Find Buffer")?;
     create_window(lua, config)
@@ -36,12 +66,171 @@ pub fn file_explorer_picker(lua: &Lua, config: mlua::Table) -> LuaResult<()> {
     let mappings = lua.create_table()?;
     let insert = lua.create_table()?;
     insert.set("<CR>", lua.create_function(picker::file_explorer::select_option)?)?;
+    set_open_mappings(&insert, lua, picker::file_explorer::open)?;
     insert.set("<BS>", lua.create_function(picker::file_explorer::backspace)?)?;
     insert.set("<Tab>", lua.create_function(picker::file_explorer::tab)?)?;
+    // `<Tab>` completes the path here, so marking moves to `<S-Tab>`.
+    insert.set("<S-Tab>", lua.create_function(functions::toggle_mark)?)?;
     mappings.set("i", insert)?;
-    config.set("filter", lua.create_function(picker::file_explorer::filter)?)?;
+    let boosts = frecency::Boosts::default();
+    let worker = Worker::default();
+    config.set(
+        "filter",
+        lua.create_function(move |lua, prompt| picker::file_explorer::filter(lua, &boosts, &worker, prompt))?,
+    )?;
     config.set("to_line", lua.create_function(picker::file_explorer::to_line)?)?;
+    config.set("to_quickfix", lua.create_function(picker::file_explorer::to_quickfix)?)?;
+    set_previewer(&config, lua, picker::file_explorer::ExplorerPreviewer)?;
     config.set("mappings", mappings)?;
     config.set("title", "Find File")?;
     create_window(lua, config)
 }
+
+pub fn live_grep_picker(lua: &Lua, config: mlua::Table) -> LuaResult<()> {
+    let mappings = lua.create_table()?;
+    let insert = lua.create_table()?;
+    insert.set("<CR>", lua.create_function(picker::live_grep::open_hit)?)?;
+    set_open_mappings(&insert, lua, picker::live_grep::open)?;
+    mappings.set("i", insert)?;
+
+    let boosts = frecency::Boosts::default();
+    let worker = Worker::default();
+    config.set(
+        "filter",
+        lua.create_function(move |lua, prompt| picker::live_grep::filter(lua, &boosts, &worker, prompt))?,
+    )?;
+    config.set("to_line", lua.create_function(picker::live_grep::to_line)?)?;
+    config.set("to_quickfix", lua.create_function(picker::live_grep::to_quickfix)?)?;
+    set_previewer(&config, lua, picker::live_grep::GrepPreviewer)?;
+    config.set("mappings", mappings)?;
+    config.set("title", "Live Grep")?;
+    create_window(lua, config)
+}
+
+/// Searches the project for the text given as the config's `search`, or else the
+/// visual selection or the word under the cursor, and lets the prompt narrow down
+/// the hits. As hits are single lines, a selection over several lines only
+/// searches for the part of it on its first line.
+pub fn grep_string_picker(lua: &Lua, config: mlua::Table) -> LuaResult<()> {
+    let text = match config.get::<_, Option<String>>("search")? {
+        Some(text) => text,
+        None => picker::grep_string::text_under_cursor(lua)?,
+    };
+    let search = picker::grep_string::Search::new(text);
+
+    let mappings = lua.create_table()?;
+    let insert = lua.create_table()?;
+    insert.set("<CR>", lua.create_function(picker::live_grep::open_hit)?)?;
+    set_open_mappings(&insert, lua, picker::live_grep::open)?;
+    mappings.set("i", insert)?;
+
+    let worker = Worker::default();
+    config.set(
+        "filter",
+        lua.create_function(move |lua, prompt| picker::grep_string::filter(lua, &search, &worker, prompt))?,
+    )?;
+    config.set("to_line", lua.create_function(picker::live_grep::to_line)?)?;
+    config.set("to_quickfix", lua.create_function(picker::live_grep::to_quickfix)?)?;
+    set_previewer(&config, lua, picker::live_grep::GrepPreviewer)?;
+    config.set("mappings", mappings)?;
+    config.set("title", "Grep String")?;
+    create_window(lua, config)
+}
+
+/// Lists the lines of the current buffer, previewing the buffer around the
+/// selected one. Only `<CR>` moves the cursor, so any other way of closing the
+/// picker leaves it where it was. There is only ever one line to jump to, so
+/// results can't be marked.
+pub fn buffer_lines_picker(lua: &Lua, config: mlua::Table) -> LuaResult<()> {
+    let buffer = Vim::new(lua).nvim_get_current_buf()?;
+    let source = Source::from_vec(picker::buffer_lines::lines(lua, buffer)?);
+
+    let mappings = lua.create_table()?;
+    let insert = lua.create_table()?;
+    insert.set("<CR>", lua.create_function(picker::buffer_lines::jump)?)?;
+    for key in ["<Tab>", "<M-a>", "<M-d>"] {
+        insert.set(key, "<Nop>")?;
+    }
+    mappings.set("i", insert)?;
+
+    let worker = Worker::default();
+    config.set(
+        "filter",
+        lua.create_function(move |lua, prompt| picker::buffer_lines::filter(lua, &source, &worker, prompt))?,
+    )?;
+    config.set("to_line", lua.create_function(picker::buffer_lines::to_line)?)?;
+    config.set("to_quickfix", lua.create_function(picker::buffer_lines::to_quickfix)?)?;
+    set_previewer(&config, lua, picker::buffer_lines::LinePreviewer)?;
+    config.set("mappings", mappings)?;
+    config.set("title", "Buffer Lines")?;
+    create_window(lua, config)
+}
+
+/// Lists the lines of every listed buffer.
+pub fn lines_picker(lua: &Lua, config: mlua::Table) -> LuaResult<()> {
+    let cwd: Option<String> = config.get("cwd")?;
+    let source = Source::from_vec(picker::buffer_lines::all_lines(lua, cwd.as_deref())?);
+
+    let mappings = lua.create_table()?;
+    let insert = lua.create_table()?;
+    insert.set("<CR>", lua.create_function(picker::buffer_lines::open_line)?)?;
+    set_open_mappings(&insert, lua, picker::buffer_lines::open)?;
+    mappings.set("i", insert)?;
+
+    let worker = Worker::default();
+    config.set(
+        "filter",
+        lua.create_function(move |lua, prompt| picker::buffer_lines::filter(lua, &source, &worker, prompt))?,
+    )?;
+    config.set("to_line", lua.create_function(picker::buffer_lines::to_line)?)?;
+    config.set("to_quickfix", lua.create_function(picker::buffer_lines::to_quickfix)?)?;
+    set_previewer(&config, lua, picker::buffer_lines::LinePreviewer)?;
+    config.set("mappings", mappings)?;
+    config.set("title", "Lines")?;
+    create_window(lua, config)
+}
+
+/// Lists the files edited recently, most recent first.
+pub fn oldfiles_picker(lua: &Lua, config: mlua::Table) -> LuaResult<()> {
+    let cwd: Option<String> = config.get("cwd")?;
+    let source = Source::from_vec(picker::oldfiles::recent_files(lua, cwd.as_deref())?);
+
+    let mappings = lua.create_table()?;
+    let insert = lua.create_table()?;
+    insert.set("<CR>", lua.create_function(picker::file::open_file)?)?;
+    set_open_mappings(&insert, lua, picker::file::open)?;
+    mappings.set("i", insert)?;
+
+    let worker = Worker::default();
+    config.set(
+        "filter",
+        lua.create_function(move |lua, prompt| picker::oldfiles::filter(lua, &source, &worker, prompt))?,
+    )?;
+    config.set("to_line", lua.create_function(picker::file::to_line)?)?;
+    config.set("to_quickfix", lua.create_function(picker::file::to_quickfix)?)?;
+    set_previewer(&config, lua, picker::file::FilePreviewer)?;
+    config.set("mappings", mappings)?;
+    config.set("title", "Recent Files")?;
+    create_window(lua, config)
+}
+
+/// Binds `<C-x>`, `<C-v>` and `<C-t>` to opening the selection in a split, a
+/// vertical split and a new tab.
+fn set_open_mappings(insert: &mlua::Table, lua: &Lua, open: fn(&Lua, OpenMode) -> LuaResult<()>) -> LuaResult<()> {
+    for (key, mode) in [
+        ("<C-x>", OpenMode::Split),
+        ("<C-v>", OpenMode::VSplit),
+        ("<C-t>", OpenMode::Tab),
+    ] {
+        insert.set(key, lua.create_function(move |lua, ()| open(lua, mode))?)?;
+    }
+    Ok(())
+}
+
+/// Uses `previewer` unless the config brings its own `preview` function.
+fn set_previewer(config: &mlua::Table, lua: &Lua, previewer: impl Previewer + 'static) -> LuaResult<()> {
+    if config.contains_key("preview")? {
+        return Ok(());
+    }
+    config.set("preview", preview::function(lua, previewer)?)
+}
@@ -0,0 +1,69 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use mlua::prelude::*;
+use mlua::Lua;
+
+use crate::frecency;
+use crate::picker::file::File;
+use crate::search::Scheme;
+use crate::sorter::{self, SorterKind};
+use crate::source::Source;
+use crate::vim::Vim;
+use crate::worker::Worker;
+use crate::Config;
+
+/// The files in `v:oldfiles` that still exist, most recently used first.
+/// With a `cwd` only those below it are kept, relative to it.
+pub fn recent_files(lua: &Lua, cwd: Option<&str>) -> LuaResult<Vec<File>> {
+    let oldfiles = Vim::new(lua).oldfiles()?;
+    Ok(restrict(oldfiles, cwd).into_iter().map(|path| File { path }).collect())
+}
+
+fn restrict(oldfiles: Vec<String>, cwd: Option<&str>) -> Vec<String> {
+    let cwd = cwd.map(|cwd| frecency::key(Path::new(cwd)));
+    oldfiles
+        .into_iter()
+        .filter(|path| Path::new(path).is_file())
+        .filter_map(|path| match &cwd {
+            Some(cwd) => Path::new(&path)
+                .strip_prefix(cwd)
+                .ok()
+                .and_then(Path::to_str)
+                .map(str::to_string),
+            None => Some(path),
+        })
+        .collect()
+}
+
+pub fn filter(lua: &Lua, source: &Source<File>, worker: &Worker, prompt: String) -> LuaResult<()> {
+    let config = Config::new(lua);
+    // The files already come in the order they were last used in.
+    let sorter = sorter::new(config.sorter.unwrap_or(SorterKind::Fuzzy), || Ok(Arc::default()))?;
+    worker.search(lua, prompt, Scheme::Path, source.clone(), |x| &x.path, sorter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_restrict() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(temp.path().join("src")).unwrap();
+        fs::write(temp.path().join("src/lib.rs"), "").unwrap();
+        let dir = fs::canonicalize(temp.path()).unwrap();
+        let existing = dir.join("src/lib.rs").to_str().unwrap().to_string();
+        let outside = std::env::current_exe().unwrap().to_str().unwrap().to_string();
+        let oldfiles = vec![
+            existing.clone(),
+            dir.join("missing.rs").to_str().unwrap().to_string(),
+            outside.clone(),
+            "term://~//1234:/bin/zsh".to_string(),
+        ];
+
+        assert_eq!(restrict(oldfiles.clone(), None), vec![existing, outside]);
+        assert_eq!(restrict(oldfiles, dir.to_str()), vec!["src/lib.rs"]);
+    }
+}
@@ -0,0 +1,220 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use mlua::prelude::*;
+use mlua::{FromLua, IntoLua, LuaSerdeExt};
+use serde::{Deserialize, Serialize};
+
+use crate::vim::{GetOptionValue, Vim};
+use crate::{functions, Config};
+
+/// Only this much of a file is read for its preview.
+const MAX_PREVIEW_BYTES: u64 = 256 * 1024;
+/// A file containing a NUL byte this early on is treated as binary, like git does.
+const BINARY_CHECK_BYTES: usize = 8000;
+
+/// What the preview window shows for an entry. A Lua `preview` function returns
+/// one of these as a table, or just a path to preview that file.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Preview {
+    /// The start of a file, highlighted by its filetype, or the part of it around `line`.
+    File { path: String, line: Option<usize> },
+    /// The current contents of a loaded buffer, centered on `line`.
+    Buffer { buffer: usize, line: Option<usize> },
+    /// Any other text, highlighted as `filetype` if given.
+    Lines {
+        lines: Vec<String>,
+        filetype: Option<String>,
+    },
+}
+
+impl<'lua> FromLua<'lua> for Preview {
+    fn from_lua(value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::String(path) => Ok(Preview::File {
+                path: path.to_str()?.to_string(),
+                line: None,
+            }),
+            value => lua.from_value(value),
+        }
+    }
+}
+
+impl<'lua> IntoLua<'lua> for Preview {
+    fn into_lua(self, lua: &'lua Lua) -> LuaResult<LuaValue<'lua>> {
+        lua.to_value(&self)
+    }
+}
+
+/// Decides what to preview for a picker's entries.
+pub trait Previewer {
+    fn preview<'lua>(&self, lua: &'lua Lua, value: LuaValue<'lua>) -> LuaResult<Option<Preview>>;
+}
+
+/// Wraps `previewer` into the `preview` function of a picker's config.
+pub fn function(lua: &Lua, previewer: impl Previewer + 'static) -> LuaResult<LuaFunction<'_>> {
+    lua.create_function(move |lua, value: LuaValue| previewer.preview(lua, value))
+}
+
+/// Opens the preview window next to the picker's `window` and remembers it in
+/// the picker's `buffer`, so that [`update`] can show the selected entry in it.
+pub fn open(lua: &Lua, buffer: usize, window: usize, config: &Config) -> LuaResult<()> {
+    let vim = Vim::new(lua);
+    let preview_buffer = vim.nvim_create_buffer(false, true)?;
+    let (columns, lines) = vim.editor_size()?;
+    let options = config.layout.preview_options(columns, lines, window);
+    let preview_window = vim.nvim_open_win(preview_buffer, false, options)?;
+
+    vim.nvim_buf_set_var(buffer, "peek_preview_buffer", LuaValue::Integer(preview_buffer as i64))?;
+    vim.nvim_buf_set_var(buffer, "peek_preview_window", LuaValue::Integer(preview_window.into()))
+}
+
+/// Closes the preview window of the picker in `buffer`, if it has one.
+pub fn close(lua: &Lua, buffer: usize) -> LuaResult<()> {
+    let vim = Vim::new(lua);
+    let Ok(window) = vim.nvim_buf_get_var::<usize>(buffer, "peek_preview_window") else {
+        return Ok(());
+    };
+    let preview_buffer: usize = vim.nvim_buf_get_var(buffer, "peek_preview_buffer")?;
+
+    if vim.nvim_win_is_valid(window)? {
+        vim.nvim_win_close(window, true)?;
+    }
+    if vim.nvim_buf_is_valid(preview_buffer)? {
+        vim.nvim_buf_delete(
+            preview_buffer,
+            crate::vim::BufferDeleteOptions {
+                force: Some(true),
+                unload: None,
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Shows the selected entry of the current picker in its preview window.
+pub fn update(lua: &Lua) -> LuaResult<()> {
+    let vim = Vim::new(lua);
+    let Ok(window) = vim.nvim_buf_get_var::<usize>(0, "peek_preview_window") else {
+        return Ok(());
+    };
+    let preview_buffer: usize = vim.nvim_buf_get_var(0, "peek_preview_buffer")?;
+    if !vim.nvim_win_is_valid(window)? {
+        return Ok(());
+    }
+
+    let config = Config::new(lua);
+    let preview = match functions::selected_value(lua, ())? {
+        Some(value) => config.preview(value)?,
+        None => None,
+    };
+
+    let (lines, filetype, line) = match preview {
+        Some(Preview::File { path, line }) => (read(Path::new(&path)), vim.filetype_match(&path)?, line),
+        Some(Preview::Buffer { buffer, line }) if vim.nvim_buf_is_valid(buffer)? => {
+            let filetype = vim.nvim_get_option_value("filetype", GetOptionValue { buf: Some(buffer) })?;
+            (vim.nvim_buf_get_lines(buffer, 0, -1, false)?, Some(filetype), line)
+        }
+        Some(Preview::Lines { lines, filetype }) => (lines, filetype, None),
+        _ => (vec![], None, None),
+    };
+
+    let last_line = lines.len().max(1);
+    vim.nvim_buf_set_lines(preview_buffer, 0, -1, false, lines)?;
+    vim.nvim_set_option_value(
+        "filetype",
+        filetype.unwrap_or_default(),
+        GetOptionValue {
+            buf: Some(preview_buffer),
+        },
+    )?;
+
+    let line = line.unwrap_or(1).clamp(1, last_line);
+    vim.nvim_win_set_cursor(window, vec![line as i32, 0])?;
+    if line > 1 {
+        let center = lua.create_function(|lua, ()| lua.load("vim.cmd('normal! zz')").exec())?;
+        vim.nvim_win_call(window, center)?;
+    }
+    Ok(())
+}
+
+/// The lines to preview `path` with: the start of the file, or a short note
+/// when it can't be shown.
+pub fn read(path: &Path) -> Vec<String> {
+    let mut contents = vec![];
+    let read = File::open(path).and_then(|file| file.take(MAX_PREVIEW_BYTES).read_to_end(&mut contents));
+    if let Err(e) = read {
+        return vec![format!("Can't preview {}: {}", path.display(), e)];
+    }
+
+    if is_binary(&contents) {
+        return vec!["Binary file".to_string()];
+    }
+
+    String::from_utf8_lossy(&contents).lines().map(str::to_string).collect()
+}
+
+pub fn is_binary(contents: &[u8]) -> bool {
+    contents[..contents.len().min(BINARY_CHECK_BYTES)].contains(&0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_preview_from_lua() {
+        let lua = Lua::new();
+        let preview = |code: &str| lua.load(code).eval::<Preview>().unwrap();
+
+        assert_eq!(
+            preview("'src/lib.rs'"),
+            Preview::File {
+                path: "src/lib.rs".to_string(),
+                line: None
+            }
+        );
+        assert_eq!(
+            preview("{ path = 'src/lib.rs', line = 4 }"),
+            Preview::File {
+                path: "src/lib.rs".to_string(),
+                line: Some(4)
+            }
+        );
+        assert_eq!(
+            preview("{ buffer = 3, line = 10 }"),
+            Preview::Buffer {
+                buffer: 3,
+                line: Some(10)
+            }
+        );
+        assert_eq!(
+            preview("{ lines = { 'a', 'b' }, filetype = 'markdown' }"),
+            Preview::Lines {
+                lines: vec!["a".to_string(), "b".to_string()],
+                filetype: Some("markdown".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn test_read() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        fs::write(dir.join("text.rs"), "fn main() {\r\n}\n").unwrap();
+        assert_eq!(read(&dir.join("text.rs")), vec!["fn main() {", "}"]);
+
+        fs::write(dir.join("binary"), [0x7f, b'E', b'L', b'F', 0, 1]).unwrap();
+        assert_eq!(read(&dir.join("binary")), vec!["Binary file"]);
+
+        let large = "x\n".repeat(MAX_PREVIEW_BYTES as usize);
+        fs::write(dir.join("large"), large).unwrap();
+        assert_eq!(read(&dir.join("large")).len(), MAX_PREVIEW_BYTES as usize / 2);
+
+        assert!(read(&dir.join("missing"))[0].starts_with("Can't preview"));
+    }
+}
@@ -0,0 +1,106 @@
+use mlua::prelude::*;
+use mlua::{FromLua, IntoLua, LuaSerdeExt};
+use serde::{Deserialize, Serialize};
+
+use crate::vim::Vim;
+use crate::{functions, Config};
+
+/// A quickfix list item. A Lua `to_quickfix` function returns one of these as
+/// a table for each entry sent to the list.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Item {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bufnr: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lnum: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub col: Option<usize>,
+    #[serde(default)]
+    pub text: String,
+}
+
+impl<'lua> FromLua<'lua> for Item {
+    fn from_lua(value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
+        lua.from_value(value)
+    }
+}
+
+impl<'lua> IntoLua<'lua> for Item {
+    fn into_lua(self, lua: &'lua Lua) -> LuaResult<LuaValue<'lua>> {
+        lua.to_value(&self)
+    }
+}
+
+#[derive(Serialize)]
+struct List {
+    title: String,
+    items: Vec<Item>,
+}
+
+/// Sends the marked results, or all of them if nothing is marked, to the
+/// quickfix list and opens it in place of the picker.
+pub fn send_to_quickfix(lua: &Lua, _: ()) -> LuaResult<()> {
+    let list = list(lua)?;
+    functions::exit(lua, ())?;
+    Vim::new(lua).setqflist(lua.to_value(&list)?)?;
+    lua.load("vim.cmd('copen')").exec()
+}
+
+/// Like [`send_to_quickfix`], with the location list of the window the picker was opened from.
+pub fn send_to_loclist(lua: &Lua, _: ()) -> LuaResult<()> {
+    let list = list(lua)?;
+    let origin_window = functions::origin_window(lua, ())?;
+    functions::exit(lua, ())?;
+
+    let vim = Vim::new(lua);
+    vim.setloclist(origin_window, lua.to_value(&list)?)?;
+    let open = lua.create_function(|lua, ()| lua.load("vim.cmd('lopen')").exec())?;
+    vim.nvim_win_call(origin_window, open)
+}
+
+fn list(lua: &Lua) -> LuaResult<List> {
+    let config = Config::new(lua);
+    let mut values = functions::marked_values(lua)?;
+    if values.is_empty() {
+        values = functions::all_values(lua)?;
+    }
+
+    let items = values
+        .iter()
+        .map(|value| config.to_quickfix(value))
+        .collect::<LuaResult<Vec<Item>>>()?;
+    Ok(List {
+        title: config.title.clone().unwrap_or("Peek".to_string()),
+        items,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_item_from_lua() {
+        let lua = Lua::new();
+        let item = lua
+            .load("{ filename = 'src/lib.rs', lnum = 3, text = 'mod vim;' }")
+            .eval::<Item>()
+            .unwrap();
+        assert_eq!(
+            item,
+            Item {
+                filename: Some("src/lib.rs".to_string()),
+                lnum: Some(3),
+                text: "mod vim;".to_string(),
+                ..Default::default()
+            }
+        );
+
+        let value = lua.to_value(&Item::default()).unwrap();
+        let table = value.as_table().unwrap();
+        assert!(!table.contains_key("filename").unwrap());
+        assert_eq!(table.get::<_, String>("text").unwrap(), "");
+    }
+}
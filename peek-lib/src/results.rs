@@ -0,0 +1,435 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::rc::Rc;
+
+use mlua::prelude::*;
+use mlua::{LuaSerdeExt, RegistryKey};
+use serde::Serialize;
+
+use crate::search::Matched;
+use crate::source::Source;
+use crate::vim::Vim;
+
+/// The results of a picker. They stay on the Rust side, and only the entries
+/// that are shown or acted on are turned into Lua values.
+pub trait Results {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The result at `index`, counting from 0.
+    fn get<'lua>(&self, lua: &'lua Lua, index: usize) -> LuaResult<Option<LuaValue<'lua>>>;
+
+    /// The results within `range`, as far as there are any.
+    fn slice<'lua>(&self, lua: &'lua Lua, range: Range<usize>) -> LuaResult<Vec<LuaValue<'lua>>> {
+        range.map_while(|i| self.get(lua, i).transpose()).collect()
+    }
+
+    /// Identifies the result at `index` by its value rather than by how it is
+    /// shown, so that marks tell apart results rendered alike and recognise a
+    /// result again once the prompt changes.
+    fn key(&self, lua: &Lua, index: usize) -> LuaResult<Option<u64>>;
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Matches ranked by the worker, pointing at candidates of the source they came from.
+pub struct Ranked<T> {
+    source: Source<T>,
+    matches: Vec<Matched<usize>>,
+}
+
+impl<T> Ranked<T> {
+    pub fn new(source: Source<T>, matches: Vec<Matched<usize>>) -> Ranked<T> {
+        Ranked { source, matches }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.matches.truncate(len);
+    }
+}
+
+impl<T> Results for Ranked<T>
+where
+    T: Serialize + Hash + Send + Sync + 'static,
+{
+    fn len(&self) -> usize {
+        self.matches.len()
+    }
+
+    fn get<'lua>(&self, lua: &'lua Lua, index: usize) -> LuaResult<Option<LuaValue<'lua>>> {
+        let Some(m) = self.matches.get(index) else {
+            return Ok(None);
+        };
+        let items = self.source.items();
+        let matched = Matched {
+            item: &items[m.item],
+            score: m.score,
+            positions: m.positions.clone(),
+        };
+        lua.to_value(&matched).map(Some)
+    }
+
+    fn key(&self, _lua: &Lua, index: usize) -> LuaResult<Option<u64>> {
+        let items = self.source.items();
+        Ok(self.matches.get(index).map(|m| hash(&items[m.item])))
+    }
+}
+
+/// Results that already are Lua values, such as those a Lua filter returns.
+pub struct Values {
+    table: RegistryKey,
+    len: usize,
+}
+
+impl Values {
+    pub fn new(lua: &Lua, values: Vec<LuaValue>) -> LuaResult<Values> {
+        let len = values.len();
+        let table = lua.create_sequence_from(values)?;
+        Ok(Values {
+            table: lua.create_registry_value(table)?,
+            len,
+        })
+    }
+}
+
+impl Results for Values {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get<'lua>(&self, lua: &'lua Lua, index: usize) -> LuaResult<Option<LuaValue<'lua>>> {
+        if index >= self.len {
+            return Ok(None);
+        }
+        let table: LuaTable = lua.registry_value(&self.table)?;
+        table.raw_get(index + 1).map(Some)
+    }
+
+    fn key(&self, lua: &Lua, index: usize) -> LuaResult<Option<u64>> {
+        match self.get(lua, index)? {
+            Some(value) => Ok(Some(hash(identity(&value)?))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Writes out `value` with the keys of its tables in order, leaving out the
+/// match positions, which change with the prompt.
+fn identity(value: &LuaValue) -> LuaResult<String> {
+    let mut id = String::new();
+    write_identity(&mut id, value, true)?;
+    Ok(id)
+}
+
+fn write_identity(id: &mut String, value: &LuaValue, top: bool) -> LuaResult<()> {
+    match value {
+        LuaValue::Table(table) => {
+            let mut entries = vec![];
+            for pair in table.clone().pairs::<LuaValue, LuaValue>() {
+                let (key, value) = pair?;
+                if top && key.as_str() == Some("positions") {
+                    continue;
+                }
+                let (mut k, mut v) = (String::new(), String::new());
+                write_identity(&mut k, &key, false)?;
+                write_identity(&mut v, &value, false)?;
+                entries.push((k, v));
+            }
+            entries.sort();
+
+            id.push('{');
+            for (k, v) in entries {
+                id.push_str(&format!("{}={},", k, v));
+            }
+            id.push('}');
+        }
+        LuaValue::String(s) => id.push_str(&format!("{:?}", s.to_string_lossy())),
+        other => id.push_str(&format!("{:?}", other)),
+    }
+    Ok(())
+}
+
+/// A picker's results and the ones marked so far, which may come from earlier
+/// results. Marked entries are only turned into Lua values when acted on.
+struct Picker {
+    results: Rc<dyn Results>,
+    keys: HashSet<u64>,
+    marked: Vec<(u64, Rc<dyn Results>, usize)>,
+}
+
+impl Picker {
+    fn mark(&mut self, key: u64, index: usize) {
+        if self.keys.insert(key) {
+            self.marked.push((key, self.results.clone(), index));
+        }
+    }
+}
+
+/// The pickers that are open, by the buffer they are in.
+#[derive(Default)]
+struct Store(HashMap<usize, Picker>);
+
+/// Makes `results` those of the picker in `buffer`, replacing the ones it had
+/// and keeping its marks. Pickers whose buffer is gone are dropped on the way.
+pub fn set(lua: &Lua, buffer: usize, results: Rc<dyn Results>) -> LuaResult<()> {
+    let vim = Vim::new(lua);
+    let mut buffers = match lua.app_data_ref::<Store>() {
+        Some(store) => store.0.keys().copied().collect(),
+        None => vec![],
+    };
+    buffers.retain(|&b| b != buffer);
+    let mut gone = vec![];
+    for b in buffers {
+        if !vim.nvim_buf_is_valid(b)? {
+            gone.push(b);
+        }
+    }
+
+    if lua.app_data_ref::<Store>().is_none() {
+        lua.set_app_data(Store::default());
+    }
+    let mut store = lua.app_data_mut::<Store>().unwrap();
+    for b in gone {
+        store.0.remove(&b);
+    }
+    match store.0.get_mut(&buffer) {
+        Some(picker) => picker.results = results,
+        None => {
+            store.0.insert(
+                buffer,
+                Picker {
+                    results,
+                    keys: HashSet::new(),
+                    marked: vec![],
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The results of the picker in `buffer`.
+pub fn get(lua: &Lua, buffer: usize) -> Option<Rc<dyn Results>> {
+    Some(lua.app_data_ref::<Store>()?.0.get(&buffer)?.results.clone())
+}
+
+/// Forgets the results of the picker in `buffer`, once it is closed.
+pub fn remove(lua: &Lua, buffer: usize) {
+    if let Some(mut store) = lua.app_data_mut::<Store>() {
+        store.0.remove(&buffer);
+    }
+}
+
+/// The result at `index` of the picker in `buffer`, counting from 0.
+pub fn value(lua: &Lua, buffer: usize, index: usize) -> LuaResult<Option<LuaValue<'_>>> {
+    match get(lua, buffer) {
+        Some(results) => results.get(lua, index),
+        None => Ok(None),
+    }
+}
+
+/// The results of the picker in `buffer` within `range`, as far as there are any.
+pub fn values(lua: &Lua, buffer: usize, range: Range<usize>) -> LuaResult<Vec<LuaValue<'_>>> {
+    match get(lua, buffer) {
+        Some(results) => results.slice(lua, range),
+        None => Ok(vec![]),
+    }
+}
+
+/// Marks the result at `index` of the picker in `buffer`, or unmarks it if it already was.
+pub fn toggle_mark(lua: &Lua, buffer: usize, index: usize) -> LuaResult<()> {
+    let Some(results) = get(lua, buffer) else {
+        return Ok(());
+    };
+    let Some(key) = results.key(lua, index)? else {
+        return Ok(());
+    };
+
+    let mut store = lua.app_data_mut::<Store>().unwrap();
+    let picker = store.0.get_mut(&buffer).unwrap();
+    match picker.keys.remove(&key) {
+        true => picker.marked.retain(|(k, _, _)| *k != key),
+        false => picker.mark(key, index),
+    }
+    Ok(())
+}
+
+/// Marks every result of the picker in `buffer`.
+pub fn mark_all(lua: &Lua, buffer: usize) -> LuaResult<()> {
+    let Some(results) = get(lua, buffer) else {
+        return Ok(());
+    };
+    let keys = (0..results.len())
+        .map(|i| results.key(lua, i))
+        .collect::<LuaResult<Option<Vec<_>>>>()?
+        .unwrap_or_default();
+
+    let mut store = lua.app_data_mut::<Store>().unwrap();
+    let picker = store.0.get_mut(&buffer).unwrap();
+    for (index, key) in keys.into_iter().enumerate() {
+        picker.mark(key, index);
+    }
+    Ok(())
+}
+
+pub fn unmark_all(lua: &Lua, buffer: usize) {
+    if let Some(mut store) = lua.app_data_mut::<Store>() {
+        if let Some(picker) = store.0.get_mut(&buffer) {
+            picker.keys.clear();
+            picker.marked.clear();
+        }
+    }
+}
+
+/// Whether each result within `range` of the picker in `buffer` is marked.
+pub fn marked(lua: &Lua, buffer: usize, range: Range<usize>) -> LuaResult<Vec<bool>> {
+    let Some(results) = get(lua, buffer) else {
+        return Ok(vec![]);
+    };
+    let keys = range
+        .map_while(|i| results.key(lua, i).transpose())
+        .collect::<LuaResult<Vec<_>>>()?;
+
+    let store = lua.app_data_ref::<Store>();
+    let marked = store.as_ref().and_then(|s| s.0.get(&buffer)).map(|p| &p.keys);
+    Ok(keys.iter().map(|k| marked.is_some_and(|m| m.contains(k))).collect())
+}
+
+/// The marked results of the picker in `buffer`, in the order they were marked.
+pub fn marked_values(lua: &Lua, buffer: usize) -> LuaResult<Vec<LuaValue<'_>>> {
+    let marked: Vec<(Rc<dyn Results>, usize)> = match lua.app_data_ref::<Store>() {
+        Some(store) => match store.0.get(&buffer) {
+            Some(picker) => picker.marked.iter().map(|(_, r, i)| (r.clone(), *i)).collect(),
+            None => vec![],
+        },
+        None => vec![],
+    };
+    marked
+        .into_iter()
+        .filter_map(|(results, index)| results.get(lua, index).transpose())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: Vec<LuaValue>) -> Vec<String> {
+        values.iter().map(|v| v.as_str().unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn test_values() {
+        let lua = Lua::new();
+        let values = ["a", "b", "c"]
+            .iter()
+            .map(|s| lua.create_string(s).map(LuaValue::String));
+        let values = Values::new(&lua, values.collect::<LuaResult<_>>().unwrap()).unwrap();
+
+        assert_eq!(values.len(), 3);
+        assert_eq!(strings(values.slice(&lua, 1..10).unwrap()), vec!["b", "c"]);
+        assert!(values.get(&lua, 3).unwrap().is_none());
+    }
+
+    #[derive(Serialize, Hash)]
+    struct Item {
+        name: &'static str,
+    }
+
+    #[test]
+    fn test_ranked() {
+        let lua = Lua::new();
+        let source = Source::from_vec(vec![Item { name: "a" }, Item { name: "b" }]);
+        let matched = |item, positions| Matched {
+            item,
+            score: 0,
+            positions,
+        };
+        let mut ranked = Ranked::new(source, vec![matched(1, vec![0]), matched(0, vec![])]);
+
+        let first = ranked.get(&lua, 0).unwrap().unwrap();
+        let first = first.as_table().unwrap();
+        assert_eq!(first.get::<_, String>("name").unwrap(), "b");
+        assert_eq!(first.get::<_, Vec<usize>>("positions").unwrap(), vec![0]);
+
+        ranked.truncate(1);
+        assert_eq!(ranked.len(), 1);
+        assert!(ranked.get(&lua, 1).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_store() {
+        let lua = Lua::new();
+        lua.load("vim = { api = { nvim_buf_is_valid = function(b) return b == 2 end } }")
+            .exec()
+            .unwrap();
+        let a = LuaValue::String(lua.create_string("a").unwrap());
+
+        set(&lua, 1, Rc::new(Values::new(&lua, vec![a.clone()]).unwrap())).unwrap();
+        assert!(get(&lua, 1).is_some());
+
+        set(&lua, 2, Rc::new(Values::new(&lua, vec![a.clone(), a]).unwrap())).unwrap();
+        assert_eq!(strings(values(&lua, 2, 0..5).unwrap()), vec!["a", "a"]);
+        assert!(get(&lua, 1).is_none());
+
+        remove(&lua, 2);
+        assert!(value(&lua, 2, 0).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_marks() {
+        let lua = Lua::new();
+        lua.load("vim = { api = { nvim_buf_is_valid = function() return true end } }")
+            .exec()
+            .unwrap();
+        let matched = |item| Matched {
+            item,
+            score: 0,
+            positions: vec![],
+        };
+        let items = vec![Item { name: "a" }, Item { name: "a" }, Item { name: "b" }];
+        let source = Source::from_vec(items);
+        set(&lua, 1, Rc::new(Ranked::new(source.clone(), vec![matched(2), matched(0)]))).unwrap();
+
+        toggle_mark(&lua, 1, 0).unwrap();
+        assert_eq!(marked(&lua, 1, 0..5).unwrap(), vec![true, false]);
+
+        // The same value is recognised in later results, wherever it ends up.
+        set(&lua, 1, Rc::new(Ranked::new(source, vec![matched(1), matched(2)]))).unwrap();
+        assert_eq!(marked(&lua, 1, 0..5).unwrap(), vec![false, true]);
+
+        mark_all(&lua, 1).unwrap();
+        let names: Vec<String> = marked_values(&lua, 1)
+            .unwrap()
+            .iter()
+            .map(|v| v.as_table().unwrap().get("name").unwrap())
+            .collect();
+        assert_eq!(names, vec!["b", "a"]);
+
+        toggle_mark(&lua, 1, 1).unwrap();
+        assert_eq!(marked(&lua, 1, 0..5).unwrap(), vec![true, false]);
+        unmark_all(&lua, 1);
+        assert!(marked_values(&lua, 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_identity() {
+        let lua = Lua::new();
+        let id = |code: &str| identity(&lua.load(code).eval::<LuaValue>().unwrap()).unwrap();
+
+        assert_eq!(id("{ path = 'a', line = 1 }"), id("{ line = 1, path = 'a' }"));
+        assert_eq!(id("{ path = 'a', positions = { 0 } }"), id("{ path = 'a' }"));
+        assert_ne!(id("{ path = 'a', line = 1 }"), id("{ path = 'a', line = 2 }"));
+        assert_ne!(id("{ path = 'a', hit = { positions = { 0 } } }"), id("{ path = 'a', hit = {} }"));
+        assert_ne!(id("'1'"), id("1"));
+    }
+}
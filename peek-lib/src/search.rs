@@ -1,41 +1,630 @@
-use std::io::Write;
+use serde::Serialize;
 
-pub fn fzf(prompt: String, stdout: Vec<u8>) -> Vec<String> {
-    let mut command = std::process::Command::new("fzf")
-        .arg("--exact")
-        .arg("--filter")
-        .arg(&prompt)
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped())
-        .spawn()
-        .unwrap();
+const SCORE_MATCH: i32 = 16;
+const SCORE_GAP_START: i32 = -3;
+const SCORE_GAP_EXTENSION: i32 = -1;
 
-    let mut stdin = command.stdin.take().expect("Failed to open stdin");
-    std::thread::spawn(move || {
-        stdin.write_all(&stdout).expect("Failed to write to stdin");
-    });
+const BONUS_BOUNDARY_WHITE: i32 = 10;
+const BONUS_PATH: i32 = 9;
+const BONUS_BOUNDARY: i32 = 8;
+const BONUS_CAMEL: i32 = 7;
+const BONUS_CONSECUTIVE: i32 = 4;
+const BONUS_FIRST_CHAR_MULTIPLIER: i32 = 2;
+/// Added for every matched character in the last component of a path.
+const BONUS_FILENAME: i32 = 4;
+/// With [`Scheme::Path`], a point is taken off for every this many characters of a path.
+const PATH_LENGTH_STEP: usize = 8;
 
-    let output = command.wait_with_output().unwrap().stdout;
-    std::str::from_utf8(&output)
-        .unwrap()
-        .lines()
-        .map(String::from)
-        .collect()
+/// A candidate that matched the prompt, along with the character indices
+/// that matched so they can be highlighted when rendered.
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct Matched<T> {
+    #[serde(flatten)]
+    pub item: T,
+    #[serde(skip)]
+    pub score: i32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub positions: Vec<usize>,
 }
 
-#[allow(clippy::ptr_arg)]
-pub fn contains(tokens: &Vec<&str>, line: &str) -> bool {
-    tokens.iter().any(|token| line.contains(token))
+#[derive(Debug, PartialEq)]
+pub struct Match {
+    pub score: i32,
+    pub positions: Vec<usize>,
+}
+
+/// How the prompt's letter case is compared against candidates.
+/// `Smart` ignores case unless a term contains an uppercase letter.
+#[derive(serde::Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CaseMode {
+    #[default]
+    Smart,
+    Ignore,
+    Respect,
+}
+
+/// How candidates are scored beyond the fuzzy match itself.
+/// `Path` treats them as file paths, favouring matches in the file name and shorter paths.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum Scheme {
+    #[default]
+    Default,
+    Path,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum TermKind {
+    Fuzzy,
+    Exact,
+    Prefix,
+    Suffix,
+    Equal,
+}
+
+#[derive(Debug, PartialEq)]
+struct Term {
+    kind: TermKind,
+    inverse: bool,
+    case_sensitive: bool,
+    pattern: Vec<char>,
+}
+
+/// A parsed prompt using fzf's extended search syntax:
+///
+/// | Token     | Match type                 |
+/// |-----------|----------------------------|
+/// | `foo`     | fuzzy                      |
+/// | `'foo`    | exact substring            |
+/// | `^foo`    | prefix                     |
+/// | `foo$`    | suffix                     |
+/// | `^foo$`   | whole text                 |
+/// | `!foo`    | does not contain `foo`     |
+/// | `!^foo`   | does not start with `foo`  |
+/// | `!foo$`   | does not end with `foo`    |
+///
+/// Space separated terms must all match, while terms joined by `|` match if any of them does.
+#[derive(Debug, Default, PartialEq)]
+pub struct Query {
+    groups: Vec<Vec<Term>>,
+    scheme: Scheme,
+}
+
+impl Query {
+    pub fn parse(prompt: &str, case_mode: CaseMode) -> Query {
+        let mut groups: Vec<Vec<Term>> = vec![];
+        let mut join_next = false;
+
+        for token in prompt.split_whitespace() {
+            if token == "|" {
+                join_next = !groups.is_empty();
+                continue;
+            }
+
+            let term = match Term::parse(token, case_mode) {
+                Some(t) => t,
+                None => continue,
+            };
+
+            match groups.last_mut() {
+                Some(group) if join_next => group.push(term),
+                _ => groups.push(vec![term]),
+            }
+            join_next = false;
+        }
+
+        Query {
+            groups,
+            scheme: Scheme::Default,
+        }
+    }
+
+    pub fn with_scheme(self, scheme: Scheme) -> Query {
+        Query { scheme, ..self }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Matches `text` against every group of the query, returning the combined
+    /// score and matched positions, or `None` if any group fails to match.
+    pub fn matches(&self, text: &str) -> Option<Match> {
+        let haystack = Haystack::new(text);
+        let mut score = 0;
+        let mut positions = vec![];
+
+        for group in self.groups.iter() {
+            let best = group
+                .iter()
+                .filter_map(|term| term.matches(&haystack))
+                .max_by_key(|m| m.score)?;
+            score += best.score;
+            positions.extend(best.positions);
+        }
+
+        positions.sort_unstable();
+        positions.dedup();
+        if self.scheme == Scheme::Path && !self.is_empty() {
+            score += path_bonus(&haystack.original, &positions);
+        }
+        Some(Match { score, positions })
+    }
+}
+
+fn path_bonus(path: &[char], positions: &[usize]) -> i32 {
+    let filename = path.iter().rposition(|c| *c == '/' || *c == '\\').map_or(0, |i| i + 1);
+    let in_filename = positions.iter().filter(|p| **p >= filename).count() as i32;
+    in_filename * BONUS_FILENAME - (path.len() / PATH_LENGTH_STEP) as i32
+}
+
+impl Term {
+    fn parse(token: &str, case_mode: CaseMode) -> Option<Term> {
+        let (inverse, token) = match token.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, token),
+        };
+
+        let (exact, token) = match token.strip_prefix('\'') {
+            Some(rest) => (true, rest),
+            None => (inverse, token),
+        };
+        let (prefix, token) = match token.strip_prefix('^') {
+            Some(rest) => (true, rest),
+            None => (false, token),
+        };
+        let (suffix, token) = match token.strip_suffix('$') {
+            Some(rest) if !rest.is_empty() || prefix => (true, rest),
+            _ => (false, token),
+        };
+
+        if token.is_empty() {
+            return None;
+        }
+
+        let kind = match (prefix, suffix) {
+            (true, true) => TermKind::Equal,
+            (true, false) => TermKind::Prefix,
+            (false, true) => TermKind::Suffix,
+            (false, false) if exact => TermKind::Exact,
+            (false, false) => TermKind::Fuzzy,
+        };
+
+        let case_sensitive = match case_mode {
+            CaseMode::Smart => token.chars().any(char::is_uppercase),
+            CaseMode::Ignore => false,
+            CaseMode::Respect => true,
+        };
+        let pattern = match case_sensitive {
+            true => token.chars().collect(),
+            false => token.chars().map(lowercase).collect(),
+        };
+
+        Some(Term {
+            kind,
+            inverse,
+            case_sensitive,
+            pattern,
+        })
+    }
+
+    fn matches(&self, haystack: &Haystack) -> Option<Match> {
+        let text = match self.case_sensitive {
+            true => &haystack.original,
+            false => &haystack.lowered,
+        };
+        let bonuses = &haystack.bonuses;
+
+        let found = match self.kind {
+            TermKind::Fuzzy => fuzzy(&self.pattern, text, bonuses),
+            TermKind::Exact => {
+                let last = text.len().checked_sub(self.pattern.len())?;
+                (0..=last)
+                    .filter_map(|start| exact_at(&self.pattern, text, bonuses, start))
+                    .max_by(|a, b| a.score.cmp(&b.score).then(b.positions.cmp(&a.positions)))
+            }
+            TermKind::Prefix => exact_at(&self.pattern, text, bonuses, 0),
+            TermKind::Suffix => {
+                let start = text.len().checked_sub(self.pattern.len())?;
+                exact_at(&self.pattern, text, bonuses, start)
+            }
+            TermKind::Equal if self.pattern.len() == text.len() => exact_at(&self.pattern, text, bonuses, 0),
+            TermKind::Equal => None,
+        };
+
+        match (self.inverse, found) {
+            (false, found) => found,
+            (true, None) => Some(Match {
+                score: 0,
+                positions: vec![],
+            }),
+            (true, Some(_)) => None,
+        }
+    }
+}
+
+struct Haystack {
+    original: Vec<char>,
+    lowered: Vec<char>,
+    bonuses: Vec<i32>,
+}
+
+impl Haystack {
+    fn new(text: &str) -> Haystack {
+        let original: Vec<char> = text.chars().collect();
+        let bonuses = (0..original.len())
+            .map(|i| bonus(if i == 0 { None } else { Some(original[i - 1]) }, original[i]))
+            .collect();
+
+        Haystack {
+            lowered: original.iter().map(|c| lowercase(*c)).collect(),
+            original,
+            bonuses,
+        }
+    }
+}
+
+/// Whether every candidate matching `current` is guaranteed to also match `previous`,
+/// so a search for `current` only has to look at the previous matches. That holds when
+/// text was appended, unless it introduced an OR, a negation or an end anchor.
+pub fn narrows(previous: &str, current: &str) -> bool {
+    current.starts_with(previous) && !current.contains(['|', '!', '$'])
+}
+
+/// Filters `items` by the prompt and orders them from best to worst match.
+/// See [`Query`] for the supported syntax. The pickers go through the worker instead;
+/// this is the one-shot version the tests use.
+#[cfg(test)]
+pub(crate) fn filter<T>(
+    prompt: &str, case_mode: CaseMode, items: Vec<T>, text: impl Fn(&T) -> &str,
+) -> Vec<Matched<T>> {
+    let query = Query::parse(prompt, case_mode);
+    let mut matches: Vec<Matched<T>> = items
+        .into_iter()
+        .filter_map(|item| {
+            let m = query.matches(text(&item))?;
+            Some(Matched {
+                item,
+                score: m.score,
+                positions: m.positions,
+            })
+        })
+        .collect();
+
+    crate::sorter::sort(&crate::sorter::ByScore, &query, &mut matches, text);
+    matches
+}
+
+/// Scores `text` against `pattern`, requiring every character of the pattern
+/// to appear in order. Matches at word boundaries, after path separators and on
+/// camelCase humps score higher, as do runs of consecutive characters.
+#[cfg(test)]
+pub(crate) fn fuzzy_match(pattern: &str, text: &str) -> Option<Match> {
+    let pattern: Vec<char> = pattern.chars().map(lowercase).collect();
+    let haystack = Haystack::new(text);
+    fuzzy(&pattern, &haystack.lowered, &haystack.bonuses)
+}
+
+fn fuzzy(pattern: &[char], text: &[char], bonuses: &[i32]) -> Option<Match> {
+    if pattern.is_empty() {
+        return Some(Match {
+            score: 0,
+            positions: vec![],
+        });
+    }
+    if !is_subsequence(pattern, text) {
+        return None;
+    }
+
+    // scores[i][j] holds the best score for pattern[..=i] with pattern[i] matched at text[j],
+    // and origins[i][j] the column pattern[i - 1] was matched at to get there.
+    let mut scores: Vec<Vec<Option<i32>>> = Vec::with_capacity(pattern.len());
+    let mut origins: Vec<Vec<usize>> = Vec::with_capacity(pattern.len());
+
+    scores.push(
+        text.iter()
+            .enumerate()
+            .map(|(j, c)| (*c == pattern[0]).then(|| SCORE_MATCH + bonuses[j] * BONUS_FIRST_CHAR_MULTIPLIER))
+            .collect(),
+    );
+    origins.push(vec![0; text.len()]);
+
+    for (i, p) in pattern.iter().enumerate().skip(1) {
+        let previous = &scores[i - 1];
+        let mut current: Vec<Option<i32>> = vec![None; text.len()];
+        let mut origin: Vec<usize> = vec![0; text.len()];
+        let mut gapped: Option<(i32, usize)> = None;
+
+        for j in 1..text.len() {
+            if j >= 2 {
+                let opened = previous[j - 2].map(|s| (s + SCORE_GAP_START, j - 2));
+                let extended = gapped.map(|(s, k)| (s + SCORE_GAP_EXTENSION, k));
+                gapped = match (opened, extended) {
+                    (Some(a), Some(b)) => Some(if a.0 >= b.0 { a } else { b }),
+                    (a, b) => a.or(b),
+                };
+            }
+
+            if text[j] != *p {
+                continue;
+            }
+
+            let consecutive = previous[j - 1].map(|s| (s + bonuses[j].max(BONUS_CONSECUTIVE), j - 1));
+            let after_gap = gapped.map(|(s, k)| (s + bonuses[j], k));
+            let best = match (consecutive, after_gap) {
+                (Some(a), Some(b)) => Some(if a.0 >= b.0 { a } else { b }),
+                (a, b) => a.or(b),
+            };
+
+            if let Some((score, from)) = best {
+                current[j] = Some(score + SCORE_MATCH);
+                origin[j] = from;
+            }
+        }
+
+        scores.push(current);
+        origins.push(origin);
+    }
+
+    let (mut column, score) = scores[pattern.len() - 1]
+        .iter()
+        .enumerate()
+        .filter_map(|(j, s)| s.map(|s| (j, s)))
+        .max_by(|(a_col, a), (b_col, b)| a.cmp(b).then(b_col.cmp(a_col)))?;
+
+    let mut positions = vec![0; pattern.len()];
+    for i in (0..pattern.len()).rev() {
+        positions[i] = column;
+        column = origins[i][column];
+    }
+
+    Some(Match { score, positions })
+}
+
+fn exact_at(pattern: &[char], text: &[char], bonuses: &[i32], start: usize) -> Option<Match> {
+    let window = text.get(start..start + pattern.len())?;
+    if window != pattern {
+        return None;
+    }
+
+    let score = (start..start + pattern.len())
+        .map(|j| match j - start {
+            0 => SCORE_MATCH + bonuses[j] * BONUS_FIRST_CHAR_MULTIPLIER,
+            _ => SCORE_MATCH + bonuses[j].max(BONUS_CONSECUTIVE),
+        })
+        .sum();
+
+    Some(Match {
+        score,
+        positions: (start..start + pattern.len()).collect(),
+    })
+}
+
+fn is_subsequence(pattern: &[char], text: &[char]) -> bool {
+    let mut pattern = pattern.iter().peekable();
+    for c in text {
+        if pattern.peek() == Some(&c) {
+            pattern.next();
+        }
+    }
+    pattern.peek().is_none()
+}
+
+pub fn lowercase(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn bonus(previous: Option<char>, current: char) -> i32 {
+    let previous = match previous {
+        Some(c) => c,
+        None => return BONUS_BOUNDARY_WHITE,
+    };
+
+    if !current.is_alphanumeric() {
+        return 0;
+    }
+
+    match previous {
+        '/' | '\\' => BONUS_PATH,
+        c if c.is_whitespace() => BONUS_BOUNDARY_WHITE,
+        c if !c.is_alphanumeric() => BONUS_BOUNDARY,
+        c if c.is_lowercase() && current.is_uppercase() => BONUS_CAMEL,
+        c if !c.is_numeric() && current.is_numeric() => BONUS_CAMEL,
+        _ => 0,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn items_of<T>(matches: Vec<Matched<T>>) -> Vec<T> {
+        matches.into_iter().map(|x| x.item).collect()
+    }
+
+    fn term(kind: TermKind, inverse: bool, pattern: &str) -> Term {
+        Term {
+            kind,
+            inverse,
+            case_sensitive: false,
+            pattern: pattern.chars().collect(),
+        }
+    }
+
+    fn parse(prompt: &str) -> Query {
+        Query::parse(prompt, CaseMode::Smart)
+    }
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert!(fuzzy_match("rmrp", "lib/rom/repo/jon.rb").is_some());
+        assert!(fuzzy_match("JON", "lib/rom/repo/jon.rb").is_some());
+        assert!(fuzzy_match("jonx", "lib/rom/repo/jon.rb").is_none());
+        assert_eq!(fuzzy_match("", "anything").unwrap().score, 0);
+    }
+
+    #[test]
+    fn test_fuzzy_match_bonuses() {
+        let boundary = fuzzy_match("rb", "repo_bar").unwrap().score;
+        let middle = fuzzy_match("rb", "xarxbx").unwrap().score;
+        assert!(boundary > middle);
+
+        let path = fuzzy_match("jon", "lib/jon.rb").unwrap().score;
+        let inside = fuzzy_match("jon", "libxjon.rb").unwrap().score;
+        assert!(path > inside);
+
+        let camel = fuzzy_match("fb", "fooBar").unwrap().score;
+        let flat = fuzzy_match("fb", "foobar").unwrap().score;
+        assert!(camel > flat);
+
+        let consecutive = fuzzy_match("repo", "repository").unwrap().score;
+        let scattered = fuzzy_match("repo", "rxexpxo").unwrap().score;
+        assert!(consecutive > scattered);
+    }
+
+    #[test]
+    fn test_fuzzy_match_positions() {
+        assert_eq!(fuzzy_match("lib", "src/lib.rs").unwrap().positions, vec![4, 5, 6]);
+        assert_eq!(fuzzy_match("sl", "src/lib.rs").unwrap().positions, vec![0, 4]);
+        assert_eq!(fuzzy_match("fb", "foo_bar_fb").unwrap().positions, vec![0, 4]);
+        assert_eq!(fuzzy_match("é", "café").unwrap().positions, vec![3]);
+    }
+
+    #[test]
+    fn test_query_parse() {
+        assert_eq!(
+            parse("foo 'bar ^baz qux$ ^all$ !nope !^pre !suf$"),
+            Query {
+                groups: vec![
+                    vec![term(TermKind::Fuzzy, false, "foo")],
+                    vec![term(TermKind::Exact, false, "bar")],
+                    vec![term(TermKind::Prefix, false, "baz")],
+                    vec![term(TermKind::Suffix, false, "qux")],
+                    vec![term(TermKind::Equal, false, "all")],
+                    vec![term(TermKind::Exact, true, "nope")],
+                    vec![term(TermKind::Prefix, true, "pre")],
+                    vec![term(TermKind::Suffix, true, "suf")],
+                ],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_query_parse_or() {
+        assert_eq!(
+            parse("a | b c | ^d"),
+            Query {
+                groups: vec![
+                    vec![term(TermKind::Fuzzy, false, "a"), term(TermKind::Fuzzy, false, "b")],
+                    vec![term(TermKind::Fuzzy, false, "c"), term(TermKind::Prefix, false, "d")],
+                ],
+                ..Default::default()
+            }
+        );
+        assert_eq!(parse("| a |"), parse("a"));
+    }
+
+    #[test]
+    fn test_query_parse_special_characters_alone() {
+        assert!(parse("! ' ^ ^$").is_empty());
+        assert_eq!(
+            parse("$"),
+            Query {
+                groups: vec![vec![term(TermKind::Fuzzy, false, "$")]],
+                ..Default::default()
+            }
+        );
+        assert!(parse("   ").is_empty());
+    }
+
+    #[test]
+    fn test_query_matches() {
+        let path = "lib/rom/repo/jon.rb";
+        let matches = |prompt: &str| parse(prompt).matches(path).is_some();
+
+        assert!(matches("rmrp"));
+        assert!(matches("'rom 'repo"));
+        assert!(!matches("'rmrp"));
+        assert!(matches("^lib"));
+        assert!(!matches("^rom"));
+        assert!(matches(".rb$"));
+        assert!(!matches("jon$"));
+        assert!(matches("^lib/rom/repo/jon.rb$"));
+        assert!(!matches("^lib$"));
+        assert!(matches("!spec"));
+        assert!(!matches("!rom"));
+        assert!(matches("!^rom"));
+        assert!(!matches("!.rb$"));
+        assert!(matches("'spec | 'rom"));
+        assert!(!matches("'spec | 'test"));
+        assert!(matches("'spec | 'rom jon"));
+        assert!(!matches("'spec | 'rom !jon"));
+    }
+
+    #[test]
+    fn test_query_positions() {
+        assert_eq!(parse("'rom").matches("lib/rom").unwrap().positions, vec![4, 5, 6]);
+        assert_eq!(parse("^li rom$").matches("lib/rom").unwrap().positions, vec![0, 1, 4, 5, 6]);
+        assert!(parse("!x").matches("lib").unwrap().positions.is_empty());
+    }
+
+    #[test]
+    fn test_path_scheme() {
+        let score = |scheme: Scheme, prompt: &str, text: &str| {
+            let query = Query::parse(prompt, CaseMode::Smart).with_scheme(scheme);
+            query.matches(text).unwrap().score
+        };
+
+        let path = |text: &str| score(Scheme::Path, "lib", text);
+        assert!(path("src/lib.rs") > path("a/b/c/d/e/f/lib.rs"));
+        assert!(path("a/b/c/d/e/f/lib.rs") > path("peek-lib/src/vim.rs"));
+        assert!(path("src/lib.rs") - score(Scheme::Default, "lib", "src/lib.rs") > 0);
+        assert_eq!(score(Scheme::Path, "", "a/b/c/d/e/f/lib.rs"), 0);
+    }
+
+    #[test]
+    fn test_case_mode() {
+        let matches =
+            |prompt: &str, case_mode: CaseMode| Query::parse(prompt, case_mode).matches("src/ReadMe.md").is_some();
+
+        assert!(matches("readme", CaseMode::Smart));
+        assert!(matches("ReadMe", CaseMode::Smart));
+        assert!(!matches("Readme", CaseMode::Smart));
+        assert!(matches("Readme", CaseMode::Ignore));
+        assert!(!matches("readme", CaseMode::Respect));
+        assert!(matches("RM", CaseMode::Respect));
+        assert!(matches("'Read", CaseMode::Smart));
+        assert!(!matches("'READ", CaseMode::Smart));
+        assert!(matches("!README", CaseMode::Smart));
+        assert!(!matches("!readme", CaseMode::Smart));
+        assert!(matches("Src readme", CaseMode::Ignore));
+    }
+
+    #[test]
+    fn test_filter() {
+        let items = vec!["src/search.rs", "src/lib.rs", "Cargo.toml"];
+
+        assert_eq!(items_of(filter("lib", CaseMode::Smart, items.clone(), |x| x)), vec!["src/lib.rs"]);
+        assert_eq!(items_of(filter("", CaseMode::Smart, items.clone(), |x| x)), items);
+        assert_eq!(items_of(filter("toml src", CaseMode::Smart, items.clone(), |x| x)), Vec::<&str>::new());
+        assert_eq!(items_of(filter("!src", CaseMode::Smart, items.clone(), |x| x)), vec!["Cargo.toml"]);
+        assert_eq!(items_of(filter("^src !lib", CaseMode::Smart, items.clone(), |x| x)), vec!["src/search.rs"]);
+        assert!(filter("zzz", CaseMode::Smart, items.clone(), |x| x).is_empty());
+        assert_eq!(filter("lib rs", CaseMode::Smart, items, |x| x)[0].positions, vec![4, 5, 6, 8, 9]);
+    }
+
     #[test]
-    fn test_containts() {
-        assert!(contains(&vec!("rom", "repo"), "lib/rom/repo/jon.rb"));
-        assert!(!contains(&vec!("rom"), "lib/repo/jon.rb"));
-        assert!(contains(&vec!("rom"), "rom"));
+    fn test_narrows() {
+        assert!(narrows("", "src"));
+        assert!(narrows("sr", "src"));
+        assert!(narrows("src", "src lib"));
+        assert!(narrows("src", "src 'lib"));
+        assert!(!narrows("src", "sr"));
+        assert!(!narrows("src", "lib"));
+        assert!(!narrows("src", "src | lib"));
+        assert!(!narrows("src", "src !lib"));
+        assert!(!narrows("!s", "!sr"));
+        assert!(!narrows("rs", "rs$"));
     }
 }
@@ -0,0 +1,164 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use mlua::prelude::*;
+
+use crate::search::{Matched, Query};
+
+/// A matched candidate as seen by a [`Sorter`].
+pub struct Candidate<'a> {
+    pub text: &'a str,
+    pub score: i32,
+}
+
+/// Decides the order results are shown in. Sorters run on the search thread,
+/// so ordering done by Lua configs goes through `Config::sort_results` instead.
+pub trait Sorter: Send + Sync {
+    fn compare(&self, query: &Query, a: &Candidate, b: &Candidate) -> Ordering;
+}
+
+/// The built-in sorters, as named by the `sorter` config option.
+#[derive(serde::Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SorterKind {
+    Fuzzy,
+    Alphabetical,
+    PathLength,
+    Frecency,
+}
+
+/// Best match first, preferring shorter candidates on equal scores. Candidates
+/// keep their original order when the query is empty.
+pub struct ByScore;
+
+impl Sorter for ByScore {
+    fn compare(&self, query: &Query, a: &Candidate, b: &Candidate) -> Ordering {
+        if query.is_empty() {
+            return Ordering::Equal;
+        }
+        b.score.cmp(&a.score).then(a.text.len().cmp(&b.text.len()))
+    }
+}
+
+pub struct Alphabetical;
+
+impl Sorter for Alphabetical {
+    fn compare(&self, _query: &Query, a: &Candidate, b: &Candidate) -> Ordering {
+        a.text.cmp(b.text)
+    }
+}
+
+/// Shortest candidate first, then by score.
+pub struct ByLength;
+
+impl Sorter for ByLength {
+    fn compare(&self, _query: &Query, a: &Candidate, b: &Candidate) -> Ordering {
+        a.text.len().cmp(&b.text.len()).then(b.score.cmp(&a.score))
+    }
+}
+
+/// Like [`ByScore`], with each candidate's frecency bonus added to its score,
+/// so often picked entries move up even before anything is typed.
+pub struct ByFrecency {
+    boosts: Arc<HashMap<String, i32>>,
+}
+
+impl ByFrecency {
+    pub fn new(boosts: Arc<HashMap<String, i32>>) -> ByFrecency {
+        ByFrecency { boosts }
+    }
+
+    fn score(&self, candidate: &Candidate) -> i32 {
+        candidate.score + self.boosts.get(candidate.text).copied().unwrap_or_default()
+    }
+}
+
+impl Sorter for ByFrecency {
+    fn compare(&self, query: &Query, a: &Candidate, b: &Candidate) -> Ordering {
+        self.score(b)
+            .cmp(&self.score(a))
+            .then_with(|| ByScore.compare(query, a, b))
+    }
+}
+
+/// Creates the built-in sorter of the given kind. `boosts` is only called for
+/// [`SorterKind::Frecency`].
+pub fn new(
+    kind: SorterKind, boosts: impl FnOnce() -> LuaResult<Arc<HashMap<String, i32>>>,
+) -> LuaResult<Box<dyn Sorter>> {
+    Ok(match kind {
+        SorterKind::Fuzzy => Box::new(ByScore),
+        SorterKind::Alphabetical => Box::new(Alphabetical),
+        SorterKind::PathLength => Box::new(ByLength),
+        SorterKind::Frecency => Box::new(ByFrecency::new(boosts()?)),
+    })
+}
+
+/// Orders `matches` with `sorter`. The sort is stable, so candidates the sorter
+/// considers equal keep their original order.
+pub fn sort<T>(sorter: &dyn Sorter, query: &Query, matches: &mut [Matched<T>], text: impl Fn(&T) -> &str) {
+    matches.sort_by(|a, b| {
+        let a = Candidate {
+            text: text(&a.item),
+            score: a.score,
+        };
+        let b = Candidate {
+            text: text(&b.item),
+            score: b.score,
+        };
+        sorter.compare(query, &a, &b)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{self, CaseMode};
+
+    fn sorted(sorter: &dyn Sorter, prompt: &str, items: &[&'static str]) -> Vec<&'static str> {
+        let query = Query::parse(prompt, CaseMode::Smart);
+        let mut matches: Vec<Matched<&str>> = items
+            .iter()
+            .filter_map(|item| {
+                let m = query.matches(item)?;
+                Some(Matched {
+                    item: *item,
+                    score: m.score,
+                    positions: m.positions,
+                })
+            })
+            .collect();
+
+        sort(sorter, &query, &mut matches, |x| x);
+        matches.into_iter().map(|m| m.item).collect()
+    }
+
+    #[test]
+    fn test_builtin_sorters() {
+        let items = ["src/search.rs", "lib.rs", "src/lib.rs", "Cargo.toml"];
+
+        assert_eq!(sorted(&ByScore, "", &items), items);
+        assert_eq!(sorted(&ByScore, "lib", &items), vec!["lib.rs", "src/lib.rs"]);
+        assert_eq!(sorted(&Alphabetical, "", &items), vec!["Cargo.toml", "lib.rs", "src/lib.rs", "src/search.rs"]);
+        assert_eq!(sorted(&ByLength, "rs", &items), vec!["lib.rs", "src/lib.rs", "src/search.rs"]);
+    }
+
+    #[test]
+    fn test_frecency_sorter() {
+        let items = ["src/search.rs", "lib.rs", "src/lib.rs"];
+        let sorter = ByFrecency::new(Arc::new(HashMap::from([("src/lib.rs".to_string(), 20)])));
+
+        assert_eq!(sorted(&sorter, "", &items), vec!["src/lib.rs", "src/search.rs", "lib.rs"]);
+        assert_eq!(sorted(&sorter, "lib", &items), vec!["src/lib.rs", "lib.rs"]);
+        assert_eq!(search::filter("lib", CaseMode::Smart, items.to_vec(), |x| x)[0].item, "lib.rs");
+    }
+
+    #[test]
+    fn test_sorter_kind() {
+        let unavailable = || Err(LuaError::RuntimeError("no store".to_string()));
+        assert!(new(SorterKind::Frecency, || Ok(Arc::default())).is_ok());
+        assert!(new(SorterKind::Frecency, unavailable).is_err());
+        assert!(new(SorterKind::Fuzzy, unavailable).is_ok());
+    }
+}
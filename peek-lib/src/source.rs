@@ -0,0 +1,165 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+/// A list of candidates that may still be growing while it is being searched.
+/// Producers append batches from a background thread and mark the source
+/// finished once they are done.
+pub struct Source<T> {
+    inner: Arc<Inner<T>>,
+}
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+struct Inner<T> {
+    id: usize,
+    items: RwLock<Vec<T>>,
+    finished: AtomicBool,
+    cancelled: AtomicBool,
+}
+
+/// The producing end of a [`Source`].
+pub struct Sink<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Clone for Source<T> {
+    fn clone(&self) -> Self {
+        Source {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> Source<T> {
+    /// A source whose candidates are all known up front.
+    pub fn from_vec(items: Vec<T>) -> Source<T> {
+        Source {
+            inner: Arc::new(Inner {
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                items: RwLock::new(items),
+                finished: AtomicBool::new(true),
+                cancelled: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Runs `producer` on a background thread, marking the source finished when it returns.
+    pub fn spawn(producer: impl FnOnce(&Sink<T>) + Send + 'static) -> Source<T> {
+        let inner = Arc::new(Inner {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            items: RwLock::new(vec![]),
+            finished: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+        });
+
+        let sink = Sink { inner: inner.clone() };
+        std::thread::spawn(move || {
+            producer(&sink);
+            sink.inner.finished.store(true, Ordering::SeqCst);
+        });
+
+        Source { inner }
+    }
+
+    /// Identifies this source, shared by all of its clones.
+    pub fn id(&self) -> usize {
+        self.inner.id
+    }
+
+    pub fn items(&self) -> RwLockReadGuard<'_, Vec<T>> {
+        self.inner.items.read().unwrap()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.inner.finished.load(Ordering::SeqCst)
+    }
+
+    /// Tells the producer to stop, even while the source is still referenced.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+impl<T> Sink<T> {
+    /// Appends a batch of candidates, returning `false` once the source was
+    /// cancelled or nobody is listening to it anymore and the producer can stop.
+    pub fn send(&self, batch: Vec<T>) -> bool {
+        if self.inner.cancelled.load(Ordering::SeqCst) {
+            return false;
+        }
+        self.inner.items.write().unwrap().extend(batch);
+        Arc::strong_count(&self.inner) > 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_from_vec() {
+        let source = Source::from_vec(vec![1, 2, 3]);
+        assert!(source.is_finished());
+        assert_eq!(*source.items(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_spawn() {
+        let (sender, receiver) = mpsc::channel::<()>();
+        let source = Source::spawn(move |sink| {
+            sink.send(vec![1, 2]);
+            receiver.recv().unwrap();
+            sink.send(vec![3]);
+        });
+
+        while source.len() < 2 {
+            std::thread::yield_now();
+        }
+        assert!(!source.is_finished());
+        assert_eq!(*source.items(), vec![1, 2]);
+
+        sender.send(()).unwrap();
+        while !source.is_finished() {
+            std::thread::yield_now();
+        }
+        assert_eq!(*source.items(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_send_without_listeners() {
+        let (go, wait) = mpsc::channel::<()>();
+        let (report, listening) = mpsc::channel();
+        let source = Source::spawn(move |sink| {
+            wait.recv().unwrap();
+            report.send(sink.send(vec![1])).unwrap();
+        });
+
+        drop(source);
+        go.send(()).unwrap();
+        assert!(!listening.recv().unwrap());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let (go, wait) = mpsc::channel::<()>();
+        let (report, listening) = mpsc::channel();
+        let source = Source::spawn(move |sink| {
+            wait.recv().unwrap();
+            report.send(sink.send(vec![1])).unwrap();
+        });
+
+        source.cancel();
+        go.send(()).unwrap();
+        assert!(!listening.recv().unwrap());
+        assert!(source.is_empty());
+    }
+}
@@ -21,6 +21,12 @@ pub struct ExtMarkOptions {
     pub hl_eol: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub end_row: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_col: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub virt_text: Option<Vec<(String, String)>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub virt_text_pos: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -45,8 +51,7 @@ pub struct BufferDeleteOptions {
     pub unload: Option<bool>,
 }
 
-#[allow(dead_code)]
-#[derive(Serialize)]
+#[derive(Serialize, Default, Debug, PartialEq)]
 pub struct WindowOptions {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub width: Option<i32>,
@@ -54,6 +59,22 @@ pub struct WindowOptions {
     pub height: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub split: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub win: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relative: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub row: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub col: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub style: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub border: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title_pos: Option<String>,
 }
 
 #[derive(Serialize, Default)]
@@ -68,6 +89,37 @@ pub struct HighlightOptions {
 
 impl mlua::UserData for WindowOptions {}
 
+/// Where a picked file or buffer is opened, relative to the current window.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OpenMode {
+    /// In the current window.
+    Edit,
+    /// In a new horizontal split.
+    Split,
+    /// In a new vertical split.
+    VSplit,
+    /// In a new tab.
+    Tab,
+}
+
+pub struct AutocmdOptions<'a> {
+    pub buffer: Option<usize>,
+    pub once: bool,
+    pub callback: Function<'a>,
+}
+
+impl<'a> AutocmdOptions<'a> {
+    pub fn to_lua_table(&'a self, lua: &'a Lua) -> Table<'a> {
+        let table = lua.create_table().unwrap();
+        if let Some(buffer) = self.buffer {
+            table.set("buffer", buffer).unwrap();
+        }
+        table.set("once", self.once).unwrap();
+        table.set("callback", self.callback.clone()).unwrap();
+        table
+    }
+}
+
 impl<'a> BufferAttachOptions<'a> {
     pub fn to_lua_table(&'a self, lua: &'a Lua) -> Table {
         let table = lua.create_table().unwrap();
@@ -108,6 +160,35 @@ impl<'a> Vim<'a> {
         func.call(())
     }
 
+    /// The size of the whole editor as `(columns, lines)`.
+    pub fn editor_size(&self) -> LuaResult<(usize, usize)> {
+        let options: Table = self.vim.get("o").expect("can't load vim.o");
+        Ok((options.get("columns")?, options.get("lines")?))
+    }
+
+    /// Replaces the quickfix list with the items and title in `what`.
+    pub fn setqflist(&self, what: LuaValue) -> LuaResult<()> {
+        let fn_func: Table = self.vim.get("fn").expect("can't load fn");
+        let func: Function = fn_func.get("setqflist").expect("can't load vim.fn.setqflist");
+
+        func.call((self.lua.create_table()?, " ", what))
+    }
+
+    /// Replaces the location list of `window` with the items and title in `what`.
+    pub fn setloclist(&self, window: usize, what: LuaValue) -> LuaResult<()> {
+        let fn_func: Table = self.vim.get("fn").expect("can't load fn");
+        let func: Function = fn_func.get("setloclist").expect("can't load vim.fn.setloclist");
+
+        func.call((window, self.lua.create_table()?, " ", what))
+    }
+
+    pub fn stdpath(&self, what: &str) -> LuaResult<String> {
+        let fn_func: Table = self.vim.get("fn").expect("can't load fn");
+        let func: Function = fn_func.get("stdpath").expect("can't load vim.fn.stdpath");
+
+        func.call(what)
+    }
+
     pub fn nvim_get_current_win(&self) -> LuaResult<usize> {
         let func: Function = self
             .api
@@ -126,6 +207,11 @@ impl<'a> Vim<'a> {
         func.call((window, buffer))
     }
 
+    pub fn nvim_win_get_height(&self, window: usize) -> LuaResult<usize> {
+        let func: Function = self.api.get("nvim_win_get_height").expect("nvim_win_get_height");
+        func.call(window)
+    }
+
     pub fn nvim_win_set_height(&self, window: usize, height: i32) -> LuaResult<()> {
         let func: Function = self.api.get("nvim_win_set_height").expect("nvim_win_set_height");
         func.call((window, height))
@@ -137,6 +223,24 @@ impl<'a> Vim<'a> {
         func.call((buffer, send_buffer, opts.to_lua_table(self.lua)))
     }
 
+    /// Shows `message` at `level`, one of the names in `vim.log.levels`.
+    pub fn notify(&self, message: &str, level: &str) -> LuaResult<()> {
+        let levels: Table = self.vim.get::<_, Table>("log")?.get("levels")?;
+        let level: i32 = levels.get(level)?;
+        let func: Function = self.vim.get("notify").expect("can't load vim.notify");
+
+        func.call((message, level))
+    }
+
+    pub fn nvim_create_autocmd(&self, event: &str, opts: AutocmdOptions) -> LuaResult<()> {
+        let func: Function = self
+            .api
+            .get("nvim_create_autocmd")
+            .expect("can't load nvim_create_autocmd");
+
+        func.call((event, opts.to_lua_table(self.lua)))
+    }
+
     pub fn nvim_buf_add_highlight(
         &self, buffer: usize, namespace: i32, hl_group: &str, line: i32, col_start: i32, col_end: i32,
     ) -> LuaResult<i32> {
@@ -274,6 +378,12 @@ impl<'a> Vim<'a> {
         func.call::<_, R>((buffer, name))
     }
 
+    pub fn nvim_buf_is_valid(&self, buffer: usize) -> LuaResult<bool> {
+        let func: Function = self.api.get("nvim_buf_is_valid").expect("can't load nvim_buf_is_valid");
+
+        func.call(buffer)
+    }
+
     pub fn nvim_buf_delete(&self, buffer: usize, opts: BufferDeleteOptions) -> LuaResult<()> {
         let func: Function = self.api.get("nvim_buf_delete").expect("can't load nvim_buf_delete");
 
@@ -292,7 +402,7 @@ impl<'a> Vim<'a> {
         func.call(())
     }
 
-    pub fn nvim_get_option_value(&self, name: &str, opts: GetOptionValue) -> LuaResult<bool> {
+    pub fn nvim_get_option_value<R: FromLuaMulti<'a>>(&self, name: &str, opts: GetOptionValue) -> LuaResult<R> {
         let func: Function = self
             .api
             .get("nvim_get_option_value")
@@ -301,6 +411,66 @@ impl<'a> Vim<'a> {
         func.call((name, self.lua.to_value(&opts)))
     }
 
+    pub fn nvim_set_option_value<V: IntoLua<'a>>(&self, name: &str, value: V, opts: GetOptionValue) -> LuaResult<()> {
+        let func: Function = self
+            .api
+            .get("nvim_set_option_value")
+            .expect("can't load nvim_set_option_value");
+
+        func.call((name, value, self.lua.to_value(&opts)))
+    }
+
+    pub fn nvim_buf_get_mark(&self, buffer: usize, name: &str) -> LuaResult<(usize, usize)> {
+        let func: Function = self.api.get("nvim_buf_get_mark").expect("can't load nvim_buf_get_mark");
+
+        func.call((buffer, name))
+    }
+
+    pub fn nvim_win_is_valid(&self, window: usize) -> LuaResult<bool> {
+        let func: Function = self.api.get("nvim_win_is_valid").expect("can't load nvim_win_is_valid");
+
+        func.call(window)
+    }
+
+    /// The short name of the current mode, e.g. `n`, `i` or `v`.
+    pub fn nvim_get_mode(&self) -> LuaResult<String> {
+        let func: Function = self.api.get("nvim_get_mode").expect("nvim_get_mode");
+        let mode: Table = func.call(())?;
+        mode.get("mode")
+    }
+
+    /// The line and byte column of the position `expr` refers to, both counting from 1.
+    pub fn getpos(&self, expr: &str) -> LuaResult<(usize, usize)> {
+        let fn_func: Table = self.vim.get("fn").expect("can't load fn");
+        let func: Function = fn_func.get("getpos").expect("can't load vim.fn.getpos");
+        let position: Vec<usize> = func.call(expr)?;
+
+        Ok((position[1], position[2]))
+    }
+
+    /// The files recorded in the shada file, most recently used first.
+    pub fn oldfiles(&self) -> LuaResult<Vec<String>> {
+        let v: Table = self.vim.get("v").expect("can't load vim.v");
+        v.get("oldfiles")
+    }
+
+    pub fn expand(&self, expr: &str) -> LuaResult<String> {
+        let fn_func: Table = self.vim.get("fn").expect("can't load fn");
+        let func: Function = fn_func.get("expand").expect("can't load vim.fn.expand");
+
+        func.call(expr)
+    }
+
+    /// Detects the filetype of `filename` from its name alone.
+    pub fn filetype_match(&self, filename: &str) -> LuaResult<Option<String>> {
+        let filetype: Table = self.vim.get("filetype").expect("can't load vim.filetype");
+        let func: Function = filetype.get("match").expect("can't load vim.filetype.match");
+        let args = self.lua.create_table()?;
+        args.set("filename", filename)?;
+
+        func.call(args)
+    }
+
     pub fn nvim_buf_get_name(&self, buffer: usize) -> LuaResult<String> {
         let func: Function = self.api.get("nvim_buf_get_name").expect("can't load nvim_buf_get_name");
 
@@ -327,9 +497,31 @@ impl<'a> Vim<'a> {
     }
 
     pub fn edit_file(&self, filename: &str) -> LuaResult<()> {
+        self.open_file(filename, OpenMode::Edit)
+    }
+
+    pub fn open_file(&self, filename: &str, mode: OpenMode) -> LuaResult<()> {
+        let name = match mode {
+            OpenMode::Edit => "edit",
+            OpenMode::Split => "split",
+            OpenMode::VSplit => "vsplit",
+            OpenMode::Tab => "tabedit",
+        };
         let cmd: Table = self.vim.get("cmd").expect("can't load vim.cmd");
-        let edit: Function = cmd.get("edit").expect("can't load vim.cmd.edit");
+        let command: Function = cmd.get(name).expect("can't load vim.cmd command");
+
+        command.call(filename)
+    }
+
+    pub fn open_buffer(&self, buffer: usize, mode: OpenMode) -> LuaResult<()> {
+        let command = match mode {
+            OpenMode::Edit => "buffer",
+            OpenMode::Split => "sbuffer",
+            OpenMode::VSplit => "vertical sbuffer",
+            OpenMode::Tab => "tab sbuffer",
+        };
+        let cmd: Function = self.vim.get("cmd").expect("can't load vim.cmd");
 
-        edit.call(filename)
+        cmd.call(format!("{} {}", command, buffer))
     }
 }
@@ -0,0 +1,157 @@
+use std::path::Path;
+
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+
+use crate::Config;
+
+pub struct WalkOptions {
+    pub hidden: bool,
+    pub follow_symlinks: bool,
+    pub max_depth: Option<usize>,
+    pub exclude: Vec<String>,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        WalkOptions {
+            hidden: true,
+            follow_symlinks: false,
+            max_depth: None,
+            exclude: vec![],
+        }
+    }
+}
+
+impl WalkOptions {
+    pub fn from_config(config: &Config) -> WalkOptions {
+        let default = WalkOptions::default();
+        WalkOptions {
+            hidden: config.hidden.unwrap_or(default.hidden),
+            follow_symlinks: config.follow_symlinks.unwrap_or(default.follow_symlinks),
+            max_depth: config.max_depth,
+            exclude: config.exclude.clone().unwrap_or(default.exclude),
+        }
+    }
+}
+
+/// Lists every file below `root`, relative to it, skipping anything matched by
+/// `.gitignore`, `.ignore` or the global git excludes as well as the `.git` directory itself.
+#[cfg(test)]
+fn walk(root: &Path, options: &WalkOptions) -> Result<Vec<String>, ignore::Error> {
+    let mut files = vec![];
+    walk_batched(root, options, usize::MAX, |batch| {
+        files.extend(batch);
+        true
+    })?;
+    Ok(files)
+}
+
+/// Lists every file below `root`, relative to it, skipping anything matched by
+/// `.gitignore`, `.ignore` or the global git excludes as well as the `.git` directory itself.
+/// The files are handed to `on_batch` in batches of up to `batch_size` as they are found. Walking stops early once `on_batch` returns `false`.
+pub fn walk_batched(
+    root: &Path, options: &WalkOptions, batch_size: usize, mut on_batch: impl FnMut(Vec<String>) -> bool,
+) -> Result<(), ignore::Error> {
+    let mut overrides = OverrideBuilder::new(root);
+    overrides.add("!.git/")?;
+    for glob in options.exclude.iter() {
+        overrides.add(&format!("!{}", glob))?;
+    }
+
+    let walker = WalkBuilder::new(root)
+        .hidden(!options.hidden)
+        .follow_links(options.follow_symlinks)
+        .max_depth(options.max_depth)
+        .overrides(overrides.build()?)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    let mut batch = vec![];
+    for entry in walker.filter_map(Result::ok) {
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+
+        let path = entry.path().strip_prefix(root).ok().and_then(|p| p.to_str());
+        if let Some(path) = path {
+            batch.push(path.to_string());
+        }
+
+        if batch.len() >= batch_size && !on_batch(std::mem::take(&mut batch)) {
+            return Ok(());
+        }
+    }
+
+    if !batch.is_empty() {
+        on_batch(batch);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn fixture() -> TempDir {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        for dir in ["src/nested", ".hidden", ".git", "target"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "README.md",
+            "src/lib.rs",
+            "src/nested/deep.rs",
+            ".hidden/secret",
+            ".git/HEAD",
+            "target/out.o",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        fs::write(root.join(".ignore"), "target/\n").unwrap();
+        temp
+    }
+
+    #[test]
+    fn test_walk() {
+        let root = fixture();
+        let files = walk(root.path(), &WalkOptions::default()).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                ".hidden/secret",
+                ".ignore",
+                "README.md",
+                "src/lib.rs",
+                "src/nested/deep.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_walk_batched() {
+        let root = fixture();
+        let mut batches = vec![];
+        walk_batched(root.path(), &WalkOptions::default(), 2, |batch| {
+            batches.push(batch);
+            batches.len() < 2
+        })
+        .unwrap();
+        assert_eq!(batches, vec![vec![".hidden/secret", ".ignore"], vec!["README.md", "src/lib.rs"]]);
+    }
+
+    #[test]
+    fn test_walk_options() {
+        let root = fixture();
+        let options = WalkOptions {
+            hidden: false,
+            max_depth: Some(2),
+            exclude: vec!["*.md".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(walk(root.path(), &options).unwrap(), vec!["src/lib.rs"]);
+    }
+}
@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mlua::prelude::*;
+use serde::Serialize;
+
+use crate::results::{Ranked, Results, Values};
+use crate::search::{self, CaseMode, Matched, Query, Scheme};
+use crate::sorter::{Candidate, Sorter};
+use crate::source::Source;
+use crate::vim::{AutocmdOptions, Vim};
+use crate::Config;
+
+const POLL_INTERVAL_MS: i32 = 5;
+const STREAM_INTERVAL: Duration = Duration::from_millis(30);
+const CHUNK_SIZE: usize = 1024;
+
+/// Runs a picker's matching on a background thread so typing never waits for it.
+/// Starting a new search cancels whichever one is still in flight, along with
+/// the producer of its source if the new search uses another one.
+#[derive(Clone, Default)]
+pub struct Worker {
+    generation: Arc<AtomicUsize>,
+    last: Arc<Mutex<Option<Snapshot>>>,
+    producing: Arc<Mutex<Option<Producing>>>,
+}
+
+/// The source of the latest search, by its id, and how to cancel it.
+type Producing = (usize, Box<dyn Fn() + Send>);
+
+/// The worker of every open picker, by the buffer it is in.
+#[derive(Default)]
+struct Workers(HashMap<usize, Worker>);
+
+/// Stops whatever the worker of the picker in `buffer` is still doing, once it is closed.
+pub fn stop(lua: &Lua, buffer: usize) {
+    let worker = lua
+        .app_data_mut::<Workers>()
+        .and_then(|mut workers| workers.0.remove(&buffer));
+    if let Some(worker) = worker {
+        worker.cancel();
+    }
+}
+
+/// The most recently published matches, kept so that a search for a longer
+/// prompt over the same source only has to look at these candidates.
+struct Snapshot {
+    prompt: String,
+    case_mode: CaseMode,
+    scheme: Scheme,
+    source: usize,
+    candidates: Vec<usize>,
+    searched: usize,
+}
+
+#[derive(Default)]
+struct Progress {
+    results: Option<Vec<Matched<usize>>>,
+    done: bool,
+}
+
+type Slot = Arc<Mutex<Progress>>;
+
+impl Worker {
+    /// Matches the candidates of `source` against `prompt` off the main thread and
+    /// shows the ranked results in the current peek buffer. While the source is
+    /// still producing, newly arrived candidates are matched as they come in and
+    /// the results are refreshed in place. Results are ordered by `sorter`.
+    pub fn search<T>(
+        &self, lua: &Lua, prompt: String, scheme: Scheme, source: Source<T>, text: fn(&T) -> &str,
+        sorter: Box<dyn Sorter>,
+    ) -> LuaResult<()>
+    where
+        T: Serialize + Hash + Send + Sync + 'static,
+    {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let buffer = Vim::new(lua).nvim_get_current_buf()?;
+        let case_mode = Config::new(lua).case_mode;
+        let slot: Slot = Arc::default();
+
+        let mut producing = self.producing.lock().unwrap();
+        if let Some((id, cancel)) = producing.take() {
+            if id != source.id() {
+                cancel();
+            }
+        }
+        let producer = source.clone();
+        *producing = Some((source.id(), Box::new(move || producer.cancel())));
+        drop(producing);
+
+        if lua.app_data_ref::<Workers>().is_none() {
+            lua.set_app_data(Workers::default());
+        }
+        lua.app_data_mut::<Workers>().unwrap().0.insert(buffer, self.clone());
+
+        let previous = self.last.lock().unwrap().as_ref().and_then(|last| {
+            let narrowable = last.source == source.id()
+                && last.case_mode == case_mode
+                && last.scheme == scheme
+                && search::narrows(&last.prompt, &prompt);
+            narrowable.then(|| (last.candidates.clone(), last.searched))
+        });
+
+        let latest = self.generation.clone();
+        let last = self.last.clone();
+        let thread_source = source.clone();
+        let thread_slot = slot.clone();
+        std::thread::spawn(move || {
+            let cancelled = || latest.load(Ordering::SeqCst) != generation;
+            let query = Query::parse(&prompt, case_mode).with_scheme(scheme);
+            let mut matches: Vec<Matched<usize>> = vec![];
+            let mut searched = 0;
+            let mut published = None;
+
+            if let Some((candidates, previously_searched)) = previous {
+                for chunk in candidates.chunks(CHUNK_SIZE) {
+                    if cancelled() {
+                        return;
+                    }
+
+                    let items = thread_source.items();
+                    for &i in chunk {
+                        if let Some(m) = query.matches(text(&items[i])) {
+                            matches.push(Matched {
+                                item: i,
+                                score: m.score,
+                                positions: m.positions,
+                            });
+                        }
+                    }
+                }
+                searched = previously_searched;
+            }
+
+            loop {
+                // Returning drops this thread's hold on the source, which lets its producer stop.
+                if cancelled() {
+                    return;
+                }
+
+                let finished = thread_source.is_finished();
+                let available = thread_source.len();
+
+                while searched < available {
+                    if cancelled() {
+                        return;
+                    }
+
+                    let end = available.min(searched + CHUNK_SIZE);
+                    let items = thread_source.items();
+                    for (i, item) in items[searched..end].iter().enumerate() {
+                        if let Some(m) = query.matches(text(item)) {
+                            matches.push(Matched {
+                                item: searched + i,
+                                score: m.score,
+                                positions: m.positions,
+                            });
+                        }
+                    }
+                    searched = end;
+                }
+
+                if finished || published != Some(searched) {
+                    let items = thread_source.items();
+                    let candidate = |m: &Matched<usize>| Candidate {
+                        text: text(&items[m.item]),
+                        score: m.score,
+                    };
+                    matches.sort_by(|a, b| sorter.compare(&query, &candidate(a), &candidate(b)));
+                    drop(items);
+
+                    let mut progress = thread_slot.lock().unwrap();
+                    progress.results = Some(matches.clone());
+                    progress.done = finished;
+                    published = Some(searched);
+                    drop(progress);
+
+                    *last.lock().unwrap() = Some(Snapshot {
+                        prompt: prompt.clone(),
+                        case_mode,
+                        scheme,
+                        source: thread_source.id(),
+                        candidates: matches.iter().map(|m| m.item).collect(),
+                        searched,
+                    });
+                }
+
+                if finished || cancelled() {
+                    return;
+                }
+                std::thread::sleep(STREAM_INTERVAL);
+            }
+        });
+
+        self.poll(lua, buffer, generation, slot, source, true)
+    }
+
+    /// Cancels the search in flight and stops the producer of its source.
+    pub fn cancel(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        if let Some((_, cancel)) = self.producing.lock().unwrap().take() {
+            cancel();
+        }
+    }
+
+    fn poll<T>(
+        &self, lua: &Lua, buffer: usize, generation: usize, slot: Slot, source: Source<T>, first: bool,
+    ) -> LuaResult<()>
+    where
+        T: Serialize + Hash + Send + Sync + 'static,
+    {
+        let vim = Vim::new(lua);
+        if self.generation.load(Ordering::SeqCst) != generation || !vim.nvim_buf_is_valid(buffer)? {
+            return Ok(());
+        }
+
+        let worker = self.clone();
+        if vim.nvim_get_current_buf()? != buffer {
+            // Results can only be shown from the picker's own buffer, so there is
+            // nothing to do until it is entered again.
+            let callback = lua.create_function(move |lua, ()| {
+                worker.poll(lua, buffer, generation, slot.clone(), source.clone(), first)
+            })?;
+            let options = AutocmdOptions {
+                buffer: Some(buffer),
+                once: true,
+                callback,
+            };
+            return vim.nvim_create_autocmd("BufEnter", options);
+        }
+
+        let mut progress = slot.lock().unwrap();
+        let done = progress.done;
+        let results = progress.results.take();
+        drop(progress);
+
+        let shown = results.is_some();
+        if let Some(results) = results {
+            let config = Config::new(lua);
+            let max_results = config.max_results.unwrap_or(usize::MAX);
+            let mut ranked = Ranked::new(source.clone(), results);
+            let results: Rc<dyn Results> = match config.has_lua_sorter() {
+                // A Lua sorter has to see every result as a Lua value before they can be cut down.
+                true => {
+                    let mut values = config.sort_results(lua, ranked.slice(lua, 0..ranked.len())?)?;
+                    values.truncate(max_results);
+                    Rc::new(Values::new(lua, values)?)
+                }
+                false => {
+                    ranked.truncate(max_results);
+                    Rc::new(ranked)
+                }
+            };
+
+            match first {
+                true => crate::show_results(lua, buffer, results)?,
+                false => crate::replace_results(lua, buffer, results)?,
+            }
+        }
+
+        if done && shown {
+            return Ok(());
+        }
+
+        let first = first && !shown;
+        let callback = lua.create_function(move |lua, ()| {
+            worker.poll(lua, buffer, generation, slot.clone(), source.clone(), first)
+        })?;
+        vim.vim_defer_fn(callback, POLL_INTERVAL_MS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sorter::{self, SorterKind};
+    use std::sync::mpsc;
+
+    fn lua() -> Lua {
+        let lua = Lua::new();
+        lua.load(
+            "vim = { api = {
+                nvim_get_current_buf = function() return 1 end,
+                nvim_buf_get_var = function() return {} end,
+                nvim_buf_is_valid = function() return false end,
+            } }",
+        )
+        .exec()
+        .unwrap();
+        lua
+    }
+
+    fn search(lua: &Lua, worker: &Worker, source: Source<String>) {
+        let sorter = sorter::new(SorterKind::Fuzzy, || Ok(Arc::default())).unwrap();
+        worker
+            .search(lua, "a".to_string(), Scheme::Default, source, |x| x.as_str(), sorter)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_superseded_source_stops() {
+        let lua = lua();
+        let worker = Worker::default();
+        let (report, listening) = mpsc::channel();
+        let source = Source::spawn(move |sink| loop {
+            let listened = sink.send(vec![]);
+            report.send(listened).unwrap();
+            if !listened {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        });
+
+        search(&lua, &worker, source);
+        assert!(listening.recv().unwrap());
+        search(&lua, &worker, Source::from_vec(vec!["a".to_string()]));
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while listening.recv().unwrap() {
+            assert!(std::time::Instant::now() < deadline, "the superseded producer kept running");
+        }
+    }
+
+    #[test]
+    fn test_stop() {
+        let lua = lua();
+        let (report, listening) = mpsc::channel();
+        let source = Source::spawn(move |sink| {
+            while sink.send(vec![]) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            report.send(()).unwrap();
+        });
+
+        search(&lua, &Worker::default(), source.clone());
+        stop(&lua, 1);
+        // The source is still referenced here, so only the cancellation can have stopped it.
+        listening.recv_timeout(Duration::from_secs(5)).unwrap();
+        drop(source);
+    }
+
+    #[test]
+    fn test_wait_for_picker_buffer() {
+        let lua = lua();
+        lua.load(
+            "local calls = 0
+            vim.api.nvim_get_current_buf = function() calls = calls + 1; return calls == 1 and 1 or 2 end
+            vim.api.nvim_buf_is_valid = function() return true end
+            vim.api.nvim_create_autocmd = function(event, opts) entered = { event, opts.buffer, opts.once } end
+            vim.defer_fn = function() deferred = true end",
+        )
+        .exec()
+        .unwrap();
+
+        search(&lua, &Worker::default(), Source::from_vec(vec!["a".to_string()]));
+        let entered: (String, usize, bool) = lua.load("unpack(entered)").eval().unwrap();
+        assert_eq!(entered, ("BufEnter".to_string(), 1, true));
+        assert!(!lua.globals().contains_key("deferred").unwrap());
+    }
+}
@@ -1,5 +1,8 @@
 use mlua::Lua;
+use peek_lib::search::CaseMode;
+use peek_lib::sorter::SorterKind;
 use peek_lib::vim::Vim;
+use peek_lib::Config;
 
 #[test]
 fn test_nvim_get_current_buffer() {
@@ -15,3 +18,32 @@ fn test_nvim_get_current_buffer() {
     let vi = Vim::new(&lua);
     assert_eq!(vi.nvim_get_current_buf().unwrap(), 1);
 }
+
+#[test]
+fn test_config_case_mode() {
+    let lua = Lua::new();
+
+    let config: Config = lua.load("{ case_mode = 'respect' }").eval().unwrap();
+    assert_eq!(config.case_mode, CaseMode::Respect);
+
+    let config: Config = lua.load("{}").eval().unwrap();
+    assert_eq!(config.case_mode, CaseMode::Smart);
+}
+
+#[test]
+fn test_config_sorter() {
+    let lua = Lua::new();
+
+    let config: Config = lua.load("{ sorter = 'path_length' }").eval().unwrap();
+    assert_eq!(config.sorter, Some(SorterKind::PathLength));
+    assert!(!config.has_lua_sorter());
+
+    let config: Config = lua.load("{ sorter = function(a, b) return a > b end }").eval().unwrap();
+    assert_eq!(config.sorter, None);
+    assert!(config.has_lua_sorter());
+
+    let values = vec![1, 3, 2].into_iter().map(mlua::Value::Integer).collect();
+    let sorted = config.sort_results(&lua, values).unwrap();
+    let sorted: Vec<i64> = sorted.iter().map(|v| v.as_i64().unwrap()).collect();
+    assert_eq!(sorted, vec![3, 2, 1]);
+}
@@ -7,11 +7,30 @@ fn peek(lua: &Lua) -> LuaResult<LuaTable> {
     let builtins = lua.create_table()?;
     builtins.set("find_file", lua.create_function(peek_lib::picker::file_picker)?)?;
     builtins.set("find_buffer", lua.create_function(peek_lib::picker::buffer_picker)?)?;
+    builtins.set("buffer_lines", lua.create_function(peek_lib::picker::buffer_lines_picker)?)?;
+    builtins.set("lines", lua.create_function(peek_lib::picker::lines_picker)?)?;
+    builtins.set("oldfiles", lua.create_function(peek_lib::picker::oldfiles_picker)?)?;
     builtins.set("file_explorer", lua.create_function(peek_lib::picker::file_explorer_picker)?)?;
+    builtins.set("grep_string", lua.create_function(peek_lib::picker::grep_string_picker)?)?;
+    builtins.set("live_grep", lua.create_function(peek_lib::picker::live_grep_picker)?)?;
 
     let functions = lua.create_table()?;
     functions.set("result_count", lua.create_function(peek_lib::functions::result_count)?)?;
     functions.set("position", lua.create_function(peek_lib::functions::position)?)?;
+    functions.set("select_down", lua.create_function(peek_lib::functions::select_down)?)?;
+    functions.set("select_up", lua.create_function(peek_lib::functions::select_up)?)?;
+    functions.set("page_down", lua.create_function(peek_lib::functions::page_down)?)?;
+    functions.set("page_up", lua.create_function(peek_lib::functions::page_up)?)?;
+    functions.set("select_first", lua.create_function(peek_lib::functions::select_first)?)?;
+    functions.set("select_last", lua.create_function(peek_lib::functions::select_last)?)?;
+    functions.set("toggle_mark", lua.create_function(peek_lib::functions::toggle_mark)?)?;
+    functions.set("mark_all", lua.create_function(peek_lib::functions::mark_all)?)?;
+    functions.set("unmark_all", lua.create_function(peek_lib::functions::unmark_all)?)?;
+    functions.set("selected_values", lua.create_function(peek_lib::functions::selected_values)?)?;
+    functions.set("send_to_quickfix", lua.create_function(peek_lib::quickfix::send_to_quickfix)?)?;
+    functions.set("send_to_loclist", lua.create_function(peek_lib::quickfix::send_to_loclist)?)?;
+    functions.set("frecency_clear", lua.create_function(peek_lib::frecency::clear)?)?;
+    functions.set("frecency_prune", lua.create_function(peek_lib::frecency::prune)?)?;
 
     exports.set("builtins", builtins)?;
     exports.set("fn", functions)?;